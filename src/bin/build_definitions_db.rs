@@ -1,77 +1,634 @@
 extern crate define3;
 extern crate getopts;
-extern crate regex;
 extern crate rusqlite;
+extern crate serde_json;
 
-use define3::parse_wikitext::parse_wikitext;
+use define3::languages::{is_language_data, parse_language_data, LanguageFilter};
+use define3::parse_html::parse_html;
+use define3::parse_wikitext::{
+    default_parts_of_speech, parse, parse_wikitext, retain, to_wikitext, walk, Node,
+};
+use define3::parse_xml::{
+    decompress, for_pages, for_pages_titled, CountingReader, ParseError, ParseErrorKind, SiteInfo,
+};
 use define3::PageContent;
-use define3::{Module, Template, Word};
+use define3::{Module, Namespace, Page, Redirect, Revision, Template, Word};
+use define3::{NS_MAIN, NS_MODULE, NS_TEMPLATE};
 
 use getopts::Options;
-use regex::Regex;
 use rusqlite::{Connection, Transaction};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::fs::File;
-use std::io::Write;
+use std::io;
+use std::io::{BufRead, BufReader, IsTerminal, Write};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+/// Runs `map` then `f` on every page of the dump, using the multistream index to spread `map`
+/// over `threads` threads if one was given. `html` means the dump is an Enterprise HTML dump
+/// rather than XML. `bytes_read` counts how much of the (compressed) dump has been read.
+/// Returns the dump's `<siteinfo>`, which HTML dumps don't have.
+#[allow(clippy::too_many_arguments)]
+fn for_each_page<T, P, F, E>(
+    xml_path: &str,
+    index_path: Option<&str>,
+    html: bool,
+    threads: usize,
+    bytes_read: &Arc<AtomicU64>,
+    map: P,
+    f: F,
+    on_error: E,
+) -> Result<SiteInfo, ParseError>
+where
+    T: Send,
+    P: Fn(Page) -> T + Sync,
+    F: FnMut(T),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    let mut f = f;
+    if let Some(index_path) = index_path {
+        return define3::parse_xml::for_pages_multistream(
+            xml_path, index_path, threads, bytes_read, map, f, on_error,
+        );
+    }
+    let stdin = io::stdin();
+    let input: Box<dyn BufRead> = if xml_path == "-" {
+        Box::new(stdin.lock())
+    } else {
+        Box::new(BufReader::new(File::open(xml_path)?))
+    };
+    let input = CountingReader::new(input, bytes_read.clone());
+    let input = decompress(input, Some(Path::new(xml_path)))?;
+    if html {
+        define3::parse_html::for_pages_in(input, |page| f(map(page)), on_error)
+            .map(|()| SiteInfo::default())
+    } else {
+        define3::parse_xml::for_pages_in(input, |page| f(map(page)), on_error)
+    }
+}
+
+#[derive(PartialEq)]
+enum ProgressStyle {
+    Bar,
+    Json,
+    None,
+}
+
+/// Reports how the build is going, either as a bar that redraws itself on stderr or as one JSON
+/// object per line on stdout.
+struct Progress {
+    style: ProgressStyle,
+    phase: &'static str,
+    start: Instant,
+    last_report: Option<Instant>,
+    bytes_read: Arc<AtomicU64>,
+    bytes_total: Option<u64>,
+    pages: u64,
+    templates: u64,
+    modules: u64,
+    words: u64,
+    meanings: u64,
+}
+
+impl Progress {
+    fn new(style: ProgressStyle, bytes_total: Option<u64>) -> Progress {
+        Progress {
+            style,
+            phase: "dump",
+            start: Instant::now(),
+            last_report: None,
+            bytes_read: Arc::new(AtomicU64::new(0)),
+            bytes_total,
+            pages: 0,
+            templates: 0,
+            modules: 0,
+            words: 0,
+            meanings: 0,
+        }
+    }
+
+    /// Reports progress if it's been a while since the last report.
+    fn tick(&mut self) {
+        let interval = match self.style {
+            ProgressStyle::Bar => Duration::from_millis(200),
+            ProgressStyle::Json => Duration::from_secs(1),
+            ProgressStyle::None => return,
+        };
+        if self
+            .last_report
+            .is_none_or(|last| last.elapsed() >= interval)
+        {
+            self.report();
+        }
+    }
+
+    /// Moves on to the next phase of the build, after the dump has been read.
+    fn set_phase(&mut self, phase: &'static str) {
+        if let Some(total) = self.bytes_total {
+            self.bytes_read.store(total, Ordering::Relaxed);
+        }
+        self.phase = phase;
+        self.report();
+        if phase == "done" && self.style == ProgressStyle::Bar {
+            eprintln!();
+        }
+    }
+
+    /// Prints a line above the bar without mangling it. With JSON progress, stdout is kept for
+    /// the JSON, so it goes to stderr instead.
+    fn println(&self, message: &str) {
+        match self.style {
+            ProgressStyle::Bar => eprint!("\r\x1b[K"),
+            ProgressStyle::Json => return eprintln!("{}", message),
+            ProgressStyle::None => (),
+        }
+        println!("{}", message);
+    }
+
+    fn report(&mut self) {
+        self.last_report = Some(Instant::now());
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let bytes_read = self.bytes_read.load(Ordering::Relaxed);
+        let pages_per_sec = if elapsed > 0.0 {
+            self.pages as f64 / elapsed
+        } else {
+            0.0
+        };
+        let fraction = self
+            .bytes_total
+            .filter(|&total| total > 0)
+            .map(|total| (bytes_read as f64 / total as f64).min(1.0));
+        let eta = fraction
+            .filter(|&fraction| fraction > 0.0 && self.phase == "dump")
+            .map(|fraction| elapsed / fraction - elapsed);
+        match self.style {
+            ProgressStyle::Json => {
+                let event = serde_json::json!({
+                    "phase": self.phase,
+                    "elapsed_secs": elapsed,
+                    "bytes_read": bytes_read,
+                    "bytes_total": self.bytes_total,
+                    "pages": self.pages,
+                    "pages_per_sec": pages_per_sec,
+                    "eta_secs": eta,
+                    "templates": self.templates,
+                    "modules": self.modules,
+                    "words": self.words,
+                    "meanings": self.meanings,
+                });
+                println!("{}", event);
+            }
+            ProgressStyle::Bar => {
+                const WIDTH: usize = 30;
+                let bar = match fraction {
+                    Some(fraction) => {
+                        let done = (fraction * WIDTH as f64) as usize;
+                        format!(
+                            "[{}{}] {:5.1}% ",
+                            "=".repeat(done),
+                            " ".repeat(WIDTH - done),
+                            fraction * 100.0
+                        )
+                    }
+                    None => String::new(),
+                };
+                let eta = eta.map_or(String::new(), |eta| {
+                    let eta = eta as u64;
+                    format!(" ETA {}:{:02}:{:02}", eta / 3600, eta / 60 % 60, eta % 60)
+                });
+                eprint!(
+                    "\r\x1b[K{}{} {} read, {:.0} pages/s{} | {} templates, {} modules, {} words, \
+                     {} meanings",
+                    bar,
+                    self.phase,
+                    format_bytes(bytes_read),
+                    pages_per_sec,
+                    eta,
+                    self.templates,
+                    self.modules,
+                    self.words,
+                    self.meanings
+                );
+            }
+            ProgressStyle::None => (),
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// What we keep about every page we save something from.
+struct PageInfo {
+    title: String,
+    namespace: i32,
+    revision: Revision,
+    /// Codes and names of the languages listed on the page, if it's a language data module.
+    languages: Vec<(String, String)>,
+}
+
+/// Whether the page in the dump is the same revision as the one already in the database.
+fn unchanged(old: &Revision, new: &Revision) -> bool {
+    if !old.sha1.is_empty() && !new.sha1.is_empty() {
+        old.sha1 == new.sha1
+    } else {
+        old.id != 0 && old.id == new.id
+    }
+}
+
+/// Reads the language table, from codes to canonical names. It's empty if there isn't one yet.
+fn get_languages(tx: &Transaction) -> HashMap<String, String> {
+    let mut stmt = match tx.prepare("SELECT code, name FROM languages") {
+        Ok(stmt) => stmt,
+        Err(_) => return HashMap::new(),
+    };
+    let rows = stmt
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+/// Finds the canonical name of a language given as a code or a name, in any case. `extra_names`
+/// are names we accept even though they aren't in `languages`.
+fn resolve_language(
+    wanted: &str,
+    languages: &HashMap<String, String>,
+    extra_names: &HashSet<String>,
+) -> Option<String> {
+    languages
+        .get(wanted)
+        .or_else(|| {
+            languages
+                .values()
+                .chain(extra_names)
+                .find(|name| name.eq_ignore_ascii_case(wanted))
+        })
+        .cloned()
+}
+
+/// What we know about languages while the dump is being read: codes and their canonical names,
+/// and which languages to read definitions from. Both get better as language data modules
+/// turn up in the dump.
+struct Languages {
+    codes: HashMap<String, String>,
+    filter: LanguageFilter,
+}
+
+impl Languages {
+    /// Picks the languages to read definitions from: the ones given with --languages, once we
+    /// know what they all are. Before then, names are taken as they're given, but codes can't be.
+    fn choose(&mut self, wanted: &[String]) {
+        let resolved: Option<HashSet<String>> = wanted
+            .iter()
+            .map(|wanted| {
+                resolve_language(wanted, &self.codes, &HashSet::new()).or_else(|| {
+                    Some(wanted.clone()).filter(|name| name.starts_with(char::is_uppercase))
+                })
+            })
+            .collect();
+        if let Some(resolved) = resolved {
+            self.filter = LanguageFilter::Only(resolved);
+        }
+    }
+}
+
+/// Drops the senses and forms in languages the filter doesn't accept. Headword templates like
+/// {{fr-noun}} set the language themselves, and while the parsers check those against the filter,
+/// this makes sure nothing that wasn't asked for gets saved.
+fn retain_languages(word: &mut Word, filter: &LanguageFilter) {
+    // Sub-senses and relations point at senses by index, so those have to follow them.
+    let mut new_indices = Vec::with_capacity(word.meanings.len());
+    let mut kept = 0;
+    for meaning in &word.meanings {
+        if filter.accepts(&meaning.language) {
+            new_indices.push(Some(kept));
+            kept += 1;
+        } else {
+            new_indices.push(None);
+        }
+    }
+    word.meanings
+        .retain(|meaning| filter.accepts(&meaning.language));
+    for meaning in &mut word.meanings {
+        meaning.parent = meaning.parent.and_then(|parent| new_indices[parent]);
+    }
+    for relation in &mut word.relations {
+        relation.sense = relation.sense.and_then(|sense| new_indices[sense]);
+    }
+    word.forms.retain(|form| filter.accepts(&form.language));
+}
+
+/// Reads the language codes in the dump's language data modules, only reading the blocks they're
+/// in if there's a multistream index. Errors are left for the full read of the dump to report.
+fn read_language_codes(xml_path: &str, index_path: Option<&str>) -> HashMap<String, String> {
+    let mut codes = HashMap::new();
+    let read = |page: Page| {
+        if page.namespace.id == NS_MODULE && is_language_data(page.name()) {
+            codes.extend(parse_language_data(&page.content));
+        }
+    };
+    let skip = |_| Ok(());
+    let _ = match index_path {
+        Some(index_path) => for_pages_titled(
+            xml_path,
+            index_path,
+            |title| {
+                title
+                    .split_once(':')
+                    .is_some_and(|(_, name)| is_language_data(name))
+            },
+            read,
+            skip,
+        ),
+        None => for_pages(xml_path, read, skip).map(|_| ()),
+    };
+    codes
+}
+
+/// Reads the revisions of all the pages already in the database, by namespace and title.
+fn get_revisions(tx: &Transaction) -> HashMap<(i32, String), Revision> {
+    let mut stmt = tx
+        .prepare("SELECT namespace, title, revision_id, timestamp, sha1, contributor FROM pages")
+        .unwrap();
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((
+                (row.get(0)?, row.get(1)?),
+                Revision {
+                    id: row.get::<_, i64>(2)? as u64,
+                    timestamp: row.get(3)?,
+                    sha1: row.get(4)?,
+                    contributor: row.get(5)?,
+                },
+            ))
+        })
+        .unwrap();
+    rows.map(|row| row.unwrap()).collect()
+}
+
+/// The part of a template page that gets transcluded: what's in `<includeonly>` if there is one,
+/// otherwise the whole page minus `<noinclude>` sections and comments.
+fn transcluded_source(content: &str) -> String {
+    let mut nodes = parse(content);
+    retain(&mut nodes, &mut |node| match node {
+        Node::Comment(_) => false,
+        Node::Tag { name, .. } => !name.eq_ignore_ascii_case("noinclude"),
+        _ => true,
+    });
+    let mut includeonly = None;
+    walk(&nodes, &mut |node| match node {
+        Node::Tag {
+            name,
+            content: Some(content),
+            ..
+        } if includeonly.is_none() && name.eq_ignore_ascii_case("includeonly") => {
+            includeonly = Some(to_wikitext(content));
+        }
+        _ => (),
+    });
+    includeonly.unwrap_or_else(|| to_wikitext(&nodes))
+}
+
+/// Removes everything saved from a page, so that it can be replaced by a newer revision.
+fn delete_page(tx: &Transaction, namespace: i32, title: &str) {
+    // Templates and modules are saved without their namespace prefix.
+    let name = match namespace {
+        NS_TEMPLATE | NS_MODULE => title.split_once(':').map_or(title, |(_, name)| name),
+        _ => title,
+    };
+    match namespace {
+        NS_TEMPLATE => {
+            tx.execute("delete from templates where name = ?1", [name])
+                .unwrap();
+        }
+        NS_MODULE => {
+            tx.execute("delete from modules where name = ?1", [name])
+                .unwrap();
+            tx.execute("delete from languages where module = ?1", [title])
+                .unwrap();
+        }
+        // Entries from other namespaces, like Reconstruction, keep their whole title.
+        _ => {
+            tx.execute(
+                "delete from examples where word in (select id from words where name = ?1)",
+                [name],
+            )
+            .unwrap();
+            tx.execute("delete from words where name = ?1", [name])
+                .unwrap();
+            tx.execute("delete from etymologies where name = ?1", [name])
+                .unwrap();
+            tx.execute("delete from pronunciations where name = ?1", [name])
+                .unwrap();
+            tx.execute("delete from translations where name = ?1", [name])
+                .unwrap();
+            tx.execute("delete from relations where name = ?1", [name])
+                .unwrap();
+            tx.execute("delete from word_forms where name = ?1", [name])
+                .unwrap();
+            tx.execute("delete from redirects where name = ?1", [name])
+                .unwrap();
+        }
+    }
+    tx.execute(
+        "delete from pages where namespace = ?1 and title = ?2",
+        rusqlite::params![namespace, title],
+    )
+    .unwrap();
+}
+
+/// Namespaces saved when `--namespaces` isn't given.
+const DEFAULT_NAMESPACES: &[i32] = &[NS_MAIN, NS_TEMPLATE, NS_MODULE];
+
+/// The namespaces to save pages from, by number or by the name the dump gives them.
+struct Namespaces {
+    ids: HashSet<i32>,
+    names: HashSet<String>,
+}
+
+impl Namespaces {
+    /// Reads a list like "main,Reconstruction,118". The namespaces every wiki has can be given
+    /// by their English names, even in dumps that call them something else, like "Modèle".
+    fn parse(list: &str) -> Namespaces {
+        let mut namespaces = Namespaces {
+            ids: HashSet::new(),
+            names: HashSet::new(),
+        };
+        for name in list.split(',') {
+            let name = name.trim().to_lowercase();
+            match name.as_str() {
+                "main" => namespaces.ids.insert(NS_MAIN),
+                "template" => namespaces.ids.insert(NS_TEMPLATE),
+                "module" => namespaces.ids.insert(NS_MODULE),
+                _ => match name.parse() {
+                    Ok(id) => namespaces.ids.insert(id),
+                    Err(_) => namespaces.names.insert(name),
+                },
+            };
+        }
+        namespaces
+    }
+
+    fn contains(&self, namespace: &Namespace) -> bool {
+        self.ids.contains(&namespace.id) || self.names.contains(&namespace.name.to_lowercase())
+    }
+}
+
+/// Bumped whenever tables change in a way that `--update` can't cope with.
+const SCHEMA_VERSION: i32 = 8;
+
+/// The schema version of the database, or `SCHEMA_VERSION` if there's nothing in it yet.
+fn schema_version(tx: &Transaction) -> i32 {
+    let tables: i64 = tx
+        .query_row(
+            "select count(*) from sqlite_master where type = 'table'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    if tables == 0 {
+        return SCHEMA_VERSION;
+    }
+    tx.query_row("PRAGMA user_version", [], |row| row.get(0))
+        .unwrap()
+}
 
 fn main() {
-    // TODO: figure out list of languages automatically
-    let languages: HashSet<&str> = [
-        "Alemannic German",
-        "Chinese",
-        "English",
-        "Esperanto",
-        "French",
-        "German",
-        "Japanese",
-        "Korean",
-        "Lojban",
-    ]
-    .iter()
-    .cloned()
-    .collect();
-
-    // TODO: figure out POS list automatically
-    let parts_of_speech: HashSet<&str> = [
-        "Adjective",
-        "Adverb",
-        "Brivla",
-        "Cmavo",
-        "Conjunction",
-        "Definitions",
-        "Gismu",
-        "Hanja",
-        "Hanzi",
-        "Infix",
-        "Initialism",
-        "Interjection",
-        "Kanji",
-        "Noun",
-        "Phrase",
-        "Proper noun",
-        "Rafsi",
-        "Romanization",
-        "Verb",
-    ]
-    .iter()
-    .cloned()
-    .collect();
+    let parts_of_speech = default_parts_of_speech();
 
     let args: Vec<String> = std::env::args().collect();
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help text");
+    opts.optopt(
+        "i",
+        "index",
+        "multistream index for the dump, to parse it in parallel",
+        "PATH_TO_enwiktionary-YYYYMMDD-pages-articles-multistream-index.txt[.bz2]",
+    );
+    opts.optopt(
+        "j",
+        "threads",
+        "number of threads to parse a multistream dump with (default: number of CPUs)",
+        "N",
+    );
+    opts.optflag(
+        "u",
+        "update",
+        "update the existing database from a newer dump, only replacing pages that changed \
+         and deleting pages that are no longer in the dump",
+    );
+    opts.optflag(
+        "a",
+        "adds-changes",
+        "update the existing database from an adds-changes dump, which only has the pages \
+         that changed, so nothing is deleted",
+    );
+    opts.optflag(
+        "",
+        "html",
+        "read a Wikimedia Enterprise HTML dump (NDJSON) instead of an XML dump",
+    );
+    opts.optflag(
+        "k",
+        "skip-errors",
+        "skip pages that can't be parsed instead of stopping the build",
+    );
+    opts.optopt(
+        "",
+        "languages",
+        "only save words in these languages, given as codes or names (e.g. de,ja)",
+        "LANGUAGES",
+    );
+    opts.optopt(
+        "",
+        "namespaces",
+        "only save pages in these namespaces, by name or number (default: main,Template,Module); \
+         pages in namespaces other than Template and Module are read as entries",
+        "NAMESPACES",
+    );
+    opts.optflag("", "exclude-templates", "don't save templates");
+    opts.optopt(
+        "",
+        "progress",
+        "how to report progress: bar (default on a terminal), json (one object per line on \
+         stdout) or none",
+        "STYLE",
+    );
     let matches = opts.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || matches.free.len() != 1 {
         let brief = format!(
-            "Usage: {} PATH_TO_enwiktionary-YYYYMMDD-pages-meta-current.xml [options]",
+            "Usage: {} PATH_TO_enwiktionary-YYYYMMDD-pages-meta-current.xml[.bz2|.gz|.zst] [options]\n\n\
+             Pass - as the path to read the dump from stdin. With --html, the path is an \
+             extracted Enterprise HTML dump (.ndjson).",
             args[0]
         );
         print!("{}", opts.usage(&brief));
         return;
     }
     let xml_path = matches.free[0].clone();
+    let index_path = matches.opt_str("i");
+    if xml_path == "-" && index_path.is_some() {
+        eprintln!("Multistream dumps can't be read from stdin, since we need to seek in them.");
+        std::process::exit(1);
+    }
+    let html = matches.opt_present("html");
+    if html && index_path.is_some() {
+        eprintln!("HTML dumps don't have a multistream index.");
+        std::process::exit(1);
+    }
+    let adds_changes = matches.opt_present("a");
+    let update = matches.opt_present("u") || adds_changes;
+    let skip_errors = matches.opt_present("k");
+    let threads = matches
+        .opt_str("j")
+        .map(|threads| threads.parse::<usize>().unwrap())
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+
+    let wanted_languages: Option<Vec<String>> = matches.opt_str("languages").map(|wanted| {
+        wanted
+            .split(',')
+            .map(|language| language.trim().to_owned())
+            .collect()
+    });
+    let mut namespaces = match matches.opt_str("namespaces") {
+        Some(list) => Namespaces::parse(&list),
+        None => Namespaces {
+            ids: DEFAULT_NAMESPACES.iter().cloned().collect(),
+            names: HashSet::new(),
+        },
+    };
+    if matches.opt_present("exclude-templates") {
+        namespaces.ids.remove(&NS_TEMPLATE);
+    }
+
+    let progress_style = match matches.opt_str("progress").as_deref() {
+        Some("bar") => ProgressStyle::Bar,
+        Some("json") => ProgressStyle::Json,
+        Some("none") => ProgressStyle::None,
+        Some(style) => {
+            eprintln!("Unknown progress style: {}", style);
+            std::process::exit(1);
+        }
+        None if io::stderr().is_terminal() => ProgressStyle::Bar,
+        None => ProgressStyle::None,
+    };
+    let bytes_total = if xml_path == "-" {
+        None
+    } else {
+        fs::metadata(&xml_path).ok().map(|metadata| metadata.len())
+    };
+    let mut progress = Progress::new(progress_style, bytes_total);
 
     let mut sqlite_path = dirs::data_dir().unwrap();
     sqlite_path.push("define3");
@@ -81,18 +638,76 @@ fn main() {
     let mut conn = Connection::open(&sqlite_path).unwrap();
     let tx = Transaction::new(&mut conn, rusqlite::TransactionBehavior::Exclusive).unwrap();
 
-    println!("Saving data to {:?}", sqlite_path);
+    progress.println(&format!("Saving data to {:?}", sqlite_path));
+
+    // Look up the languages asked for before the table is dropped, so that we can stick to them
+    // while parsing. Anything we can't find yet is sorted out once the dump has been read.
+    let mut languages = Languages {
+        codes: get_languages(&tx),
+        filter: LanguageFilter::Any,
+    };
+    if update && !languages.codes.is_empty() {
+        languages.filter = LanguageFilter::Only(languages.codes.values().cloned().collect());
+    }
+    // Headword templates are read with the language codes, so they all have to be known before
+    // any entries are: otherwise what a page gets would depend on which modules were read before
+    // it, and with several threads, on timing. Only stdin can't be read twice, but it's read in
+    // order, so there the language data is picked up as it comes.
+    let read_ahead = !html && xml_path != "-";
+    if read_ahead {
+        progress.println("Reading language data");
+        languages
+            .codes
+            .extend(read_language_codes(&xml_path, index_path.as_deref()));
+    }
+    if let Some(wanted) = &wanted_languages {
+        languages.choose(wanted);
+    }
+    let languages = RwLock::new(languages);
 
-    let mut count: u64 = 0;
+    if update && schema_version(&tx) != SCHEMA_VERSION {
+        eprintln!(
+            "{:?} was built by another version of build_definitions_db. Build it again without \
+             --update.",
+            sqlite_path
+        );
+        std::process::exit(1);
+    }
 
-    let mut templates: HashMap<String, String> = HashMap::new();
-    let mut modules: HashMap<String, String> = HashMap::new();
+    if !update {
+        tx.execute_batch(
+            "DROP TABLE IF EXISTS pages;
+             DROP TABLE IF EXISTS templates;
+             DROP TABLE IF EXISTS modules;
+             DROP TABLE IF EXISTS words;
+             DROP TABLE IF EXISTS examples;
+             DROP TABLE IF EXISTS etymologies;
+             DROP TABLE IF EXISTS pronunciations;
+             DROP TABLE IF EXISTS translations;
+             DROP TABLE IF EXISTS relations;
+             DROP TABLE IF EXISTS word_forms;
+             DROP TABLE IF EXISTS redirects;
+             DROP TABLE IF EXISTS languages;
+             DROP TABLE IF EXISTS siteinfo;",
+        )
+        .unwrap();
+    }
 
-    println!("Pass 1: Collecting templates and modules");
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS pages (
+             title          text not null,
+             namespace      integer not null,
+             revision_id    integer not null,
+             timestamp      text not null,
+             sha1           text not null,
+             contributor    text
+         )",
+        [],
+    )
+    .unwrap();
 
-    tx.execute("DROP TABLE IF EXISTS templates", []).unwrap();
     tx.execute(
-        "CREATE TABLE templates (
+        "CREATE TABLE IF NOT EXISTS templates (
              name           text not null,
              content        text not null
          )",
@@ -100,9 +715,8 @@ fn main() {
     )
     .unwrap();
 
-    tx.execute("DROP TABLE IF EXISTS modules", []).unwrap();
     tx.execute(
-        "CREATE TABLE modules (
+        "CREATE TABLE IF NOT EXISTS modules (
              name           text not null,
              content        text not null
          )",
@@ -110,122 +724,587 @@ fn main() {
     )
     .unwrap();
 
-    let re_noinclude = Regex::new(r"<noinclude>(?P<text>(?s:.)*?)</noinclude>").unwrap();
-    let re_includeonly = Regex::new(r"<includeonly>(?P<text>(?s:.)*?)</includeonly>").unwrap();
-    let re_html_comment = Regex::new(r"<!--(?s:.)*?-->").unwrap();
-    // TODO: combine link REs into one
-    let re_display_link = Regex::new(r"\[\[[^\]]*?\|(?P<text>.*?)\]\]").unwrap();
-    let re_link = Regex::new(r"\[\[(?P<text>.*?)\]\]").unwrap();
-    // This technically doesn't work if some jerk decided to format a single quote.
-    let re_bold = Regex::new(r"'''(?P<text>[^']*?)'''").unwrap();
-    let re_italic = Regex::new(r"''(?P<text>[^']*?)''").unwrap();
-
-    define3::parse_xml::for_pages(&xml_path, |page| {
-        if page.title.starts_with("Template:") {
-            let content = page.content;
-            let content = re_noinclude.replace_all(&content, "");
-            let content = re_html_comment.replace_all(&content, "");
-            let content = content.into_owned();
-            let content = match re_includeonly.captures(&content) {
-                None => content.clone(),
-                Some(captures) => captures.name("text").unwrap().as_str().to_owned(),
-            };
-            let title = &page.title[9..];
-            tx.execute(
-                "insert into templates (name, content) values (?1, ?2)",
-                &[&title, &content.as_str()],
-            )
-            .unwrap();
-            templates.insert(title.to_owned(), content);
-        } else if page.title.starts_with("Module:") {
-            let title = &page.title[7..];
-            tx.execute(
-                "insert into modules (name, content) values (?1, ?2)",
-                [&title, &page.content.as_str()],
-            )
-            .unwrap();
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS words (
+             id             integer primary key,
+             name           text not null,
+             language       text not null,
+             part_of_speech text not null,
+             gender         text,
+             definition     text not null,
+             depth          integer not null,
+             ordinal        text not null,
+             parent         integer,
+             etymology      integer,
+             form_of        text
+         )",
+        [],
+    )
+    .unwrap();
 
-            println!("Saved module: {}", page.title);
-            let path = format!("modules/{}.lua", page.title);
-            let path = Path::new(&path);
-            fs::create_dir_all(path.parent().unwrap()).unwrap();
-            let mut file = File::create(path).unwrap();
-            file.write_all(page.content.as_bytes()).unwrap();
-            modules.insert(title.to_owned(), page.content);
-        }
-    });
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS examples (
+             word           integer not null,
+             text           text not null,
+             translation    text,
+             source         text,
+             year           text
+         )",
+        [],
+    )
+    .unwrap();
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS etymologies (
+             name           text not null,
+             language       text not null,
+             number         integer not null,
+             text           text not null
+         )",
+        [],
+    )
+    .unwrap();
 
-    println!("Pass 2: Collecting words");
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS pronunciations (
+             name           text not null,
+             language       text not null,
+             etymology      integer,
+             kind           text not null,
+             value          text not null,
+             accent         text
+         )",
+        [],
+    )
+    .unwrap();
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS translations (
+             name            text not null,
+             language        text not null,
+             part_of_speech  text,
+             gloss           text,
+             code            text not null,
+             word            text not null,
+             gender          text,
+             transliteration text
+         )",
+        [],
+    )
+    .unwrap();
+
+    // Edges from a word to a related word. `word` is the id of the sense they're for, if known.
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS relations (
+             name           text not null,
+             language       text not null,
+             etymology      integer,
+             part_of_speech text,
+             kind           text not null,
+             target         text not null,
+             word           integer,
+             gloss          text
+         )",
+        [],
+    )
+    .unwrap();
 
-    tx.execute("DROP TABLE IF EXISTS words", []).unwrap();
+    // Inflected forms of words. `name` is the page that says so, which is either the lemma's or,
+    // for entries like "past tense of go", the form's.
     tx.execute(
-        "CREATE TABLE words (
+        "CREATE TABLE IF NOT EXISTS word_forms (
              name           text not null,
              language       text not null,
              part_of_speech text not null,
-             gender         text,
-             definition     text not null
+             lemma          text not null,
+             form           text not null,
+             tags           text not null
          )",
         [],
     )
     .unwrap();
 
-    define3::parse_xml::for_pages(&xml_path, |page| {
-        let page_content = match page.title.split(':').next() {
-            Some("Template") => Box::new(PageContent::Template(Template {
-                name: page.title,
-                content: page.content,
-            })),
-            Some("Module") => Box::new(PageContent::Module(Module {
-                name: page.title,
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS redirects (
+             name           text not null,
+             target         text not null
+         )",
+        [],
+    )
+    .unwrap();
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS languages (
+             code           text not null,
+             name           text not null,
+             module         text not null
+         )",
+        [],
+    )
+    .unwrap();
+
+    tx.execute(
+        "CREATE TABLE IF NOT EXISTS siteinfo (
+             base           text not null
+         )",
+        [],
+    )
+    .unwrap();
+
+    let old_revisions = if update {
+        get_revisions(&tx)
+    } else {
+        HashMap::new()
+    };
+    let mut seen: HashSet<(i32, String)> = HashSet::new();
+    let mut skipped: u64 = 0;
+
+    let parse_page = |page: Page| {
+        let mut info = PageInfo {
+            title: page.title.clone(),
+            namespace: page.namespace.id,
+            revision: page.revision.clone(),
+            languages: Vec::new(),
+        };
+        let key = (info.namespace, info.title.clone());
+        if old_revisions
+            .get(&key)
+            .is_some_and(|old| unchanged(old, &page.revision))
+        {
+            return (info, None);
+        }
+        // We need the languages whether or not modules are being saved.
+        if page.namespace.id == NS_MODULE && is_language_data(page.name()) {
+            info.languages = parse_language_data(&page.content);
+        }
+        if !info.languages.is_empty() && !read_ahead {
+            let mut languages = languages.write().unwrap();
+            languages.codes.extend(info.languages.iter().cloned());
+            if let Some(wanted) = &wanted_languages {
+                languages.choose(wanted);
+            }
+        }
+        if !namespaces.contains(&page.namespace) {
+            return (info, None);
+        }
+        let page_content = match page.namespace.id {
+            NS_TEMPLATE => {
+                let content = transcluded_source(&page.content);
+                Some(PageContent::Template(Template {
+                    name: page.name().to_owned(),
+                    content,
+                }))
+            }
+            NS_MODULE => Some(PageContent::Module(Module {
+                name: page.name().to_owned(),
                 src: page.content,
             })),
-            _ => {
-                let meanings = parse_wikitext(page.content, &languages, &parts_of_speech);
-                Box::new(PageContent::Word(Word {
+            _ if page.redirect.is_some() => {
+                let target = page.redirect.unwrap();
+                // Redirects to a section, like "colour#English", just go to the page.
+                let target = target.split('#').next().unwrap_or("").to_owned();
+                Some(PageContent::Redirect(Redirect {
                     name: page.title,
-                    meanings: meanings,
+                    target,
                 }))
             }
+            _ => {
+                let languages = languages.read().unwrap();
+                let mut word = if html {
+                    parse_html(
+                        &page.title,
+                        &page.content,
+                        &languages.filter,
+                        &parts_of_speech,
+                    )
+                } else {
+                    parse_wikitext(
+                        &page.title,
+                        page.content,
+                        &languages.filter,
+                        &languages.codes,
+                        &parts_of_speech,
+                    )
+                };
+                retain_languages(&mut word, &languages.filter);
+                Some(PageContent::Word(word))
+            }
         };
-        match *page_content {
-            PageContent::Word(word) => {
-                count += 1;
-                if count % 1000000 == 0 {
-                    println!("{}: {}", count, word.name);
+        (info, page_content)
+    };
+
+    let index_path = index_path.as_deref();
+    let bytes_read = progress.bytes_read.clone();
+    let bar = progress.style == ProgressStyle::Bar;
+    let on_error = |e: ParseError| {
+        if !skip_errors {
+            return Err(e);
+        }
+        if bar {
+            eprint!("\r\x1b[K");
+        }
+        eprintln!("Skipping page: {}", e);
+        skipped += 1;
+        Ok(())
+    };
+
+    let result = for_each_page(
+        &xml_path,
+        index_path,
+        html,
+        threads,
+        &bytes_read,
+        parse_page,
+        |(info, page_content)| {
+            progress.pages += 1;
+            progress.tick();
+            if update && !adds_changes {
+                seen.insert((info.namespace, info.title.clone()));
+            }
+            if !info.languages.is_empty() {
+                tx.execute("delete from languages where module = ?1", [&info.title])
+                    .unwrap();
+                for (code, name) in &info.languages {
+                    tx.execute(
+                        "insert into languages (code, name, module) values (?1, ?2, ?3)",
+                        [code, name, &info.title],
+                    )
+                    .unwrap();
+                }
+            }
+            let page_content = match page_content {
+                None => return,
+                Some(page_content) => page_content,
+            };
+            if old_revisions.contains_key(&(info.namespace, info.title.clone())) {
+                delete_page(&tx, info.namespace, &info.title);
+            }
+            // Most entries have nothing in the languages we're after, and there's no point in
+            // keeping track of them.
+            if let PageContent::Word(word) = &page_content {
+                if word.meanings.is_empty() {
+                    return;
                 }
-                for meaning in &word.meanings {
-                    let defn = &meaning.definition;
-                    //let defn = re_link.replace_all(&defn, "\x1b[0;36m$x\x1b[0m");
-                    let defn = re_display_link.replace_all(&defn, "$text");
-                    let defn = re_link.replace_all(&defn, "$text");
-                    let defn = re_html_comment.replace_all(&defn, "");
-                    let defn = re_bold.replace_all(&defn, "$text");
-                    let defn = re_italic.replace_all(&defn, "$text");
+            }
+            tx.execute(
+                "insert into pages (title, namespace, revision_id, timestamp, sha1, contributor)
+             values (?1, ?2, ?3, ?4, ?5, ?6)",
+                rusqlite::params![
+                    info.title,
+                    info.namespace,
+                    info.revision.id as i64,
+                    info.revision.timestamp,
+                    info.revision.sha1,
+                    info.revision.contributor,
+                ],
+            )
+            .unwrap();
+            match page_content {
+                PageContent::Template(template) => {
+                    progress.templates += 1;
                     tx.execute(
-                        "insert into words (name, language, part_of_speech, gender, definition)
-                 values (?1, ?2, ?3, ?4, ?5)",
-                        &[
-                            &word.name,
-                            &meaning.language,
-                            &meaning.part_of_speech,
-                            meaning.gender.as_ref().unwrap_or(&"".to_string()),
-                            &defn.into_owned(),
-                        ],
+                        "insert into templates (name, content) values (?1, ?2)",
+                        [&template.name, &template.content],
+                    )
+                    .unwrap();
+                }
+                PageContent::Module(module) => {
+                    tx.execute(
+                        "insert into modules (name, content) values (?1, ?2)",
+                        [&module.name, &module.src],
+                    )
+                    .unwrap();
+
+                    progress.modules += 1;
+                    progress.println(&format!("Saved module: Module:{}", module.name));
+                    let path = format!("modules/Module:{}.lua", module.name);
+                    let path = Path::new(&path);
+                    fs::create_dir_all(path.parent().unwrap()).unwrap();
+                    let mut file = File::create(path).unwrap();
+                    file.write_all(module.src.as_bytes()).unwrap();
+                }
+                PageContent::Redirect(redirect) => {
+                    tx.execute(
+                        "insert into redirects (name, target) values (?1, ?2)",
+                        [&redirect.name, &redirect.target],
                     )
                     .unwrap();
                 }
+                PageContent::Word(word) => {
+                    progress.words += 1;
+                    progress.meanings += word.meanings.len() as u64;
+                    // Sub-senses point at the id of their parent sense.
+                    let mut ids: Vec<i64> = Vec::new();
+                    for meaning in &word.meanings {
+                        tx.execute(
+                            "insert into words (name, language, part_of_speech, gender, definition,
+                                                depth, ordinal, parent, etymology, form_of)
+                 values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+                            rusqlite::params![
+                                word.name,
+                                meaning.language,
+                                meaning.part_of_speech,
+                                meaning.gender.as_deref().unwrap_or(""),
+                                meaning.definition,
+                                meaning.depth as i64,
+                                meaning.ordinal,
+                                meaning.parent.map(|parent| ids[parent]),
+                                meaning.etymology.map(|number| number as i64),
+                                meaning.form_of,
+                            ],
+                        )
+                        .unwrap();
+                        let id = tx.last_insert_rowid();
+                        ids.push(id);
+                        for example in &meaning.examples {
+                            tx.execute(
+                                "insert into examples (word, text, translation, source, year)
+                 values (?1, ?2, ?3, ?4, ?5)",
+                                rusqlite::params![
+                                    id,
+                                    example.text,
+                                    example.translation,
+                                    example.source,
+                                    example.year,
+                                ],
+                            )
+                            .unwrap();
+                        }
+                    }
+                    for etymology in &word.etymologies {
+                        tx.execute(
+                            "insert into etymologies (name, language, number, text)
+                 values (?1, ?2, ?3, ?4)",
+                            rusqlite::params![
+                                word.name,
+                                etymology.language,
+                                etymology.number as i64,
+                                etymology.text,
+                            ],
+                        )
+                        .unwrap();
+                    }
+                    for pronunciation in &word.pronunciations {
+                        tx.execute(
+                            "insert into pronunciations (name, language, etymology, kind, value, accent)
+                 values (?1, ?2, ?3, ?4, ?5, ?6)",
+                            rusqlite::params![
+                                word.name,
+                                pronunciation.language,
+                                pronunciation.etymology.map(|number| number as i64),
+                                pronunciation.kind.name(),
+                                pronunciation.value,
+                                pronunciation.accent,
+                            ],
+                        )
+                        .unwrap();
+                    }
+                    for translation in &word.translations {
+                        tx.execute(
+                            "insert into translations (name, language, part_of_speech, gloss, code,
+                                                       word, gender, transliteration)
+                 values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                            rusqlite::params![
+                                word.name,
+                                translation.language,
+                                translation.part_of_speech,
+                                translation.gloss,
+                                translation.code,
+                                translation.word,
+                                translation.gender,
+                                translation.transliteration,
+                            ],
+                        )
+                        .unwrap();
+                    }
+                    for relation in &word.relations {
+                        tx.execute(
+                            "insert into relations (name, language, etymology, part_of_speech, kind,
+                                                    target, word, gloss)
+                 values (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+                            rusqlite::params![
+                                word.name,
+                                relation.language,
+                                relation.etymology.map(|number| number as i64),
+                                relation.part_of_speech,
+                                relation.kind.name(),
+                                relation.target,
+                                relation.sense.map(|sense| ids[sense]),
+                                relation.gloss,
+                            ],
+                        )
+                        .unwrap();
+                    }
+                    for form in &word.forms {
+                        tx.execute(
+                            "insert into word_forms (name, language, part_of_speech, lemma, form,
+                                                     tags)
+                 values (?1, ?2, ?3, ?4, ?5, ?6)",
+                            [
+                                &word.name,
+                                &form.language,
+                                &form.part_of_speech,
+                                &form.lemma,
+                                &form.form,
+                                &form.tags,
+                            ],
+                        )
+                        .unwrap();
+                    }
+                }
             }
-            _ => (),
+        },
+        on_error,
+    );
+    let siteinfo = result.unwrap_or_else(|e| {
+        if progress.style == ProgressStyle::Bar {
+            eprintln!();
+        }
+        eprintln!("Error reading dump: {}", e);
+        if let ParseErrorKind::UnexpectedEof = e.kind {
+            eprintln!("The dump ended early, so nothing was saved.");
+        } else {
+            eprintln!("Nothing was saved. Pass --skip-errors to skip pages like this one.");
         }
+        std::process::exit(1);
     });
+    // HTML dumps don't say which wiki they're from, so keep whatever an earlier build found.
+    if let Some(base) = siteinfo.base {
+        tx.execute("DELETE FROM siteinfo", []).unwrap();
+        tx.execute("INSERT INTO siteinfo (base) VALUES (?)", [&base])
+            .unwrap();
+    }
+    progress.set_phase("finish");
+    if skipped > 0 {
+        progress.println(&format!(
+            "Skipped {} pages that couldn't be parsed",
+            skipped
+        ));
+    }
+
+    // A page that couldn't be read isn't gone from the dump, and there's no telling which one it
+    // was, so nothing can be deleted.
+    if update && !adds_changes && skipped > 0 {
+        progress.println(
+            "Not deleting pages that are no longer in the dump, since some of it couldn't be read",
+        );
+    } else if update && !adds_changes {
+        let mut deleted = 0;
+        for (namespace, title) in old_revisions.keys() {
+            // HTML dumps only have entries, so they say nothing about templates and modules.
+            if html && *namespace != NS_MAIN {
+                continue;
+            }
+            if !seen.contains(&(*namespace, title.clone())) {
+                delete_page(&tx, *namespace, title);
+                deleted += 1;
+            }
+        }
+        progress.println(&format!(
+            "Deleted {} pages that are no longer in the dump",
+            deleted
+        ));
+    }
+
+    // Until we saw the language data, every level 2 heading was taken to be a language, so get
+    // rid of the ones that weren't. This is also where language codes in --languages get
+    // resolved if we didn't know them before.
+    let languages = get_languages(&tx);
+    let mut pruned = 0;
+    if languages.is_empty() {
+        progress.println(
+            "No language data (Module:languages/data*) was found, so every level 2 heading was \
+             kept as a language",
+        );
+    } else {
+        pruned += tx
+            .execute(
+                "delete from words where language not in (select name from languages)",
+                [],
+            )
+            .unwrap();
+    }
+    if let Some(wanted) = &wanted_languages {
+        let mut stmt = tx.prepare("SELECT DISTINCT language FROM words").unwrap();
+        let saved: HashSet<String> = stmt
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|row| row.unwrap())
+            .collect();
+        let mut names = HashSet::new();
+        for wanted in wanted {
+            match resolve_language(wanted, &languages, &saved) {
+                Some(name) => {
+                    names.insert(name);
+                }
+                None => progress.println(&format!("Unknown language: {}", wanted)),
+            }
+        }
+        if names.is_empty() {
+            eprintln!("None of the languages given with --languages are known; nothing was saved.");
+            std::process::exit(1);
+        }
+        for language in saved.difference(&names) {
+            pruned += tx
+                .execute("delete from words where language = ?1", [language])
+                .unwrap();
+        }
+    }
+    if pruned > 0 {
+        tx.execute_batch(
+            "delete from examples where word not in (select id from words);
+             delete from etymologies where not exists (select 1 from words
+                 where words.name = etymologies.name and words.language = etymologies.language);
+             delete from pronunciations where not exists (select 1 from words
+                 where words.name = pronunciations.name
+                 and words.language = pronunciations.language);
+             delete from translations where not exists (select 1 from words
+                 where words.name = translations.name
+                 and words.language = translations.language);
+             delete from relations where not exists (select 1 from words
+                 where words.name = relations.name and words.language = relations.language);
+             delete from word_forms where not exists (select 1 from words
+                 where words.name = word_forms.name and words.language = word_forms.language);",
+        )
+        .unwrap();
+        // Entries that lost all their definitions aren't worth keeping track of.
+        tx.execute(
+            "delete from pages where namespace not in (?1, ?2)
+             and title not in (select name from words)
+             and title not in (select name from redirects)",
+            [NS_TEMPLATE, NS_MODULE],
+        )
+        .unwrap();
+        progress.println(&format!(
+            "Removed {} definitions that aren't in the languages we're after",
+            pruned
+        ));
+    }
 
     tx.execute_batch(
-        "create index words_name_idx on words(name);
-         create index words_language_idx on words(language);
-         create index words_part_of_speech_idx on words(part_of_speech);",
+        "create index if not exists words_name_idx on words(name);
+         create index if not exists words_language_idx on words(language);
+         create index if not exists words_part_of_speech_idx on words(part_of_speech);
+         create index if not exists examples_word_idx on examples(word);
+         create index if not exists etymologies_name_idx on etymologies(name);
+         create index if not exists pronunciations_name_idx on pronunciations(name);
+         create index if not exists translations_name_idx on translations(name);
+         create index if not exists relations_name_idx on relations(name);
+         create index if not exists relations_target_idx on relations(target);
+         create index if not exists word_forms_name_idx on word_forms(name);
+         create index if not exists word_forms_lemma_idx on word_forms(lemma);
+         create index if not exists word_forms_form_idx on word_forms(form);
+         create index if not exists redirects_name_idx on redirects(name);
+         create index if not exists templates_name_idx on templates(name);
+         create index if not exists modules_name_idx on modules(name);
+         create index if not exists pages_title_idx on pages(title);
+         create index if not exists languages_code_idx on languages(code);
+         create index if not exists languages_name_idx on languages(name);",
     )
     .unwrap();
 
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)
+        .unwrap();
     tx.commit().unwrap();
+    progress.set_phase("done");
 }
@@ -1,54 +1,468 @@
 extern crate colored;
 extern crate define3;
 extern crate getopts;
-extern crate nom;
-extern crate regex;
 extern crate rusqlite;
 extern crate textwrap;
 
-use define3::Meaning;
+use define3::parse_wikitext::{parse, parse_form_of, to_text, Node};
+use define3::{Example, Meaning, RelationKind, Revision, NS_MAIN};
 
 use colored::*;
-use getopts::Options;
-use regex::{Captures, Regex};
+use getopts::{Matches, Options};
 use rusqlite::Connection;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
 use std::path::Path;
 
-fn get_defns_by_lang(
-    conn: &Connection,
-    word: &str,
-) -> Box<BTreeMap<String, BTreeMap<(String, Option<String>), Vec<String>>>> {
+/// Senses, with their ids.
+type Senses = Vec<(i64, Meaning)>;
+
+/// Definitions grouped by language, then by etymology, then by part of speech and gender.
+type Definitions =
+    BTreeMap<String, BTreeMap<Option<usize>, BTreeMap<(String, Option<String>), Senses>>>;
+
+/// Related words by kind and the sense they're said to be for.
+type RelatedWords = BTreeMap<(RelationKind, Option<String>), Vec<String>>;
+
+/// Inflected forms by their tags, in the order they were found.
+type Inflections = Vec<(String, Vec<String>)>;
+
+/// What's printed along with the definitions.
+#[derive(Default)]
+struct Details {
+    /// Etymologies by language and number, for --etymology.
+    etymologies: BTreeMap<(String, usize), String>,
+    /// IPA by language and etymology.
+    ipa: BTreeMap<(String, Option<usize>), Vec<String>>,
+    examples: bool,
+    /// Related words of the kinds asked for, by the id of the sense they're for.
+    sense_relations: HashMap<i64, RelatedWords>,
+    /// Related words that aren't tied to a sense, by language, etymology and part of speech.
+    relations: HashMap<(String, Option<usize>, Option<String>), RelatedWords>,
+    /// Inflected forms by language and part of speech, for --inflections.
+    inflections: HashMap<(String, String), Inflections>,
+}
+
+fn get_examples(conn: &Connection, word_id: i64) -> Vec<Example> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT text, translation, source, year FROM examples WHERE word = ?1 ORDER BY rowid",
+        )
+        .unwrap();
+    stmt.query_map([word_id], |row| {
+        Ok(Example {
+            text: row.get(0)?,
+            translation: row.get(1)?,
+            source: row.get(2)?,
+            year: row.get(3)?,
+        })
+    })
+    .unwrap()
+    .map(|example| example.unwrap())
+    .collect()
+}
+
+/// Every meaning of `word`, by language. Examples are only loaded if `examples` is set, since
+/// they're often most of the data and are only shown with `--examples`.
+fn get_defns_by_lang(conn: &Connection, word: &str, examples: bool) -> Box<Definitions> {
     let mut stmt = conn
-        .prepare("SELECT language, part_of_speech, definition, gender FROM words WHERE name = ?1")
+        .prepare(
+            "SELECT language, part_of_speech, definition, gender, depth, ordinal, id, etymology,
+                    form_of
+             FROM words
+             WHERE name = ?1 ORDER BY rowid",
+        )
         .unwrap();
     let word_iter = stmt
-        .query_map(&[&word], |row| {
-            Ok(Meaning {
+        .query_map([&word], |row| {
+            let id = row.get(6).unwrap();
+            let meaning = Meaning {
                 language: row.get(0).unwrap(),
                 part_of_speech: row.get(1).unwrap(),
                 definition: row.get(2).unwrap(),
                 gender: row.get(3).unwrap(),
-            })
+                depth: row.get::<_, i64>(4).unwrap() as usize,
+                ordinal: row.get(5).unwrap(),
+                // Rows don't know where their parent ended up in this list, and the ordinals say
+                // as much anyway.
+                parent: None,
+                examples: if examples {
+                    get_examples(conn, id)
+                } else {
+                    Vec::new()
+                },
+                etymology: row.get::<_, Option<i64>>(7).unwrap().map(|n| n as usize),
+                form_of: row.get(8).unwrap(),
+            };
+            Ok((id, meaning))
         })
         .unwrap();
 
-    let mut langs: BTreeMap<String, BTreeMap<(String, Option<String>), Vec<String>>> =
-        BTreeMap::new();
+    let mut langs: Definitions = BTreeMap::new();
 
     for meaning in word_iter {
-        let meaning = meaning.unwrap();
+        let (id, meaning) = meaning.unwrap();
         langs
-            .entry(meaning.language)
-            .or_insert(BTreeMap::new())
-            .entry((meaning.part_of_speech, meaning.gender))
-            .or_insert(Vec::new())
-            .push(meaning.definition);
+            .entry(meaning.language.clone())
+            .or_default()
+            .entry(meaning.etymology)
+            .or_default()
+            .entry((meaning.part_of_speech.clone(), meaning.gender.clone()))
+            .or_default()
+            .push((id, meaning));
     }
     Box::new(langs)
 }
 
+/// The etymologies of a word, by language and number.
+fn get_etymologies(conn: &Connection, word: &str) -> BTreeMap<(String, usize), String> {
+    let mut stmt = conn
+        .prepare("SELECT language, number, text FROM etymologies WHERE name = ?1")
+        .unwrap();
+    stmt.query_map([word], |row| {
+        Ok(((row.get(0)?, row.get::<_, i64>(1)? as usize), row.get(2)?))
+    })
+    .unwrap()
+    .map(|etymology| etymology.unwrap())
+    .collect()
+}
+
+/// The IPA of a word, like "/bæŋk/ (UK)", by language and etymology.
+fn get_ipa(conn: &Connection, word: &str) -> BTreeMap<(String, Option<usize>), Vec<String>> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT language, etymology, value, accent FROM pronunciations
+             WHERE name = ?1 AND kind = 'IPA' ORDER BY rowid",
+        )
+        .unwrap();
+    let rows = stmt
+        .query_map([word], |row| {
+            let language: String = row.get(0)?;
+            let etymology = row.get::<_, Option<i64>>(1)?.map(|n| n as usize);
+            let ipa: String = row.get(2)?;
+            let ipa = match row.get::<_, Option<String>>(3)? {
+                Some(accent) => format!("{} ({})", ipa, accent),
+                None => ipa,
+            };
+            Ok(((language, etymology), ipa))
+        })
+        .unwrap();
+    let mut result: BTreeMap<(String, Option<usize>), Vec<String>> = BTreeMap::new();
+    for row in rows {
+        let (key, ipa) = row.unwrap();
+        let ipas = result.entry(key).or_default();
+        if !ipas.contains(&ipa) {
+            ipas.push(ipa);
+        }
+    }
+    result
+}
+
+/// Translations grouped by the language and part of speech of the word translated, then by the
+/// sense they're for, in the order the entry has them.
+type Translations = BTreeMap<(String, String), Vec<(Option<String>, Vec<String>)>>;
+
+/// The code of a language given by code or by name, like "fr" for "French".
+fn language_code(conn: &Connection, language: &str) -> String {
+    conn.query_row(
+        "SELECT code FROM languages WHERE name = ?1",
+        [language],
+        |row| row.get(0),
+    )
+    .unwrap_or_else(|_| language.to_owned())
+}
+
+fn get_translations(conn: &Connection, word: &str, code: &str) -> Translations {
+    let mut stmt = conn
+        .prepare(
+            "SELECT language, part_of_speech, gloss, word, gender, transliteration
+             FROM translations WHERE name = ?1 AND code = ?2 ORDER BY rowid",
+        )
+        .unwrap();
+    let rows = stmt
+        .query_map([word, code], |row| {
+            let mut translation: String = row.get(3)?;
+            if let Some(gender) = row.get::<_, Option<String>>(4)? {
+                translation = format!("{} {}", translation, gender.italic());
+            }
+            if let Some(transliteration) = row.get::<_, Option<String>>(5)? {
+                translation = format!("{} ({})", translation, transliteration);
+            }
+            Ok((
+                row.get(0)?,
+                row.get::<_, Option<String>>(1)?.unwrap_or_default(),
+                row.get(2)?,
+                translation,
+            ))
+        })
+        .unwrap();
+    let mut result: Translations = BTreeMap::new();
+    for row in rows {
+        let (language, pos, gloss, translation) = row.unwrap();
+        let glosses = result.entry((language, pos)).or_default();
+        match glosses.last_mut() {
+            Some((last, translations)) if *last == gloss => translations.push(translation),
+            _ => glosses.push((gloss, vec![translation])),
+        }
+    }
+    result
+}
+
+fn print_translations(translations: &Translations) {
+    let textwrap_opts = textwrap::Options::new(80)
+        .initial_indent("    ")
+        .subsequent_indent("      ");
+    let mut last_language = None;
+    for ((language, pos), glosses) in translations {
+        if last_language != Some(language) {
+            println!("{}", language.green().bold());
+            last_language = Some(language);
+        }
+        println!("  {}", pos.white());
+        for (gloss, words) in glosses {
+            let line = match gloss {
+                Some(gloss) => format!("{}: {}", gloss, words.join(", ")),
+                None => words.join(", "),
+            };
+            println!("{}", textwrap::fill(&line, &textwrap_opts));
+        }
+    }
+
+    if translations.is_empty() {
+        println!("No translations found.");
+    }
+}
+
+/// Fills in the related words of the given kinds in `details`.
+fn get_relations(conn: &Connection, word: &str, kinds: &[RelationKind], details: &mut Details) {
+    let mut stmt = conn
+        .prepare(
+            "SELECT language, etymology, part_of_speech, kind, target, word, gloss
+             FROM relations WHERE name = ?1 ORDER BY rowid",
+        )
+        .unwrap();
+    let rows = stmt
+        .query_map([word], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Option<i64>>(1)?.map(|n| n as usize),
+                row.get::<_, Option<String>>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, String>(4)?,
+                row.get::<_, Option<i64>>(5)?,
+                row.get::<_, Option<String>>(6)?,
+            ))
+        })
+        .unwrap();
+    for row in rows {
+        let (language, etymology, pos, kind, target, sense, gloss) = row.unwrap();
+        let kind = match RelationKind::from_name(&kind) {
+            Some(kind) if kinds.contains(&kind) => kind,
+            _ => continue,
+        };
+        let related = match sense {
+            Some(sense) => details.sense_relations.entry(sense).or_default(),
+            None => details
+                .relations
+                .entry((language, etymology, pos))
+                .or_default(),
+        };
+        let targets = related.entry((kind, gloss)).or_default();
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+}
+
+fn print_relations(related: Option<&RelatedWords>, indent: &str) {
+    let textwrap_opts = textwrap::Options::new(80)
+        .initial_indent(indent)
+        .subsequent_indent(indent);
+    for ((kind, gloss), targets) in related.into_iter().flatten() {
+        let label = match gloss {
+            Some(gloss) => format!("{} ({}):", kind.heading(), gloss),
+            None => format!("{}:", kind.heading()),
+        };
+        let line = format!("{} {}", label.dimmed(), targets.join(", "));
+        println!("{}", textwrap::fill(&line, &textwrap_opts));
+    }
+}
+
+/// The inflected forms of `word`, by language and part of speech. Forms come from the lemma's
+/// headword and from the forms' own form-of entries; when both list a form, like "went" as
+/// "simple past" and "past tense" of "go", only the headword's tags are kept.
+fn get_inflections(conn: &Connection, word: &str) -> HashMap<(String, String), Inflections> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT language, part_of_speech, tags, form, name = lemma FROM word_forms
+             WHERE lemma = ?1 ORDER BY name != lemma, rowid",
+        )
+        .unwrap();
+    let rows = stmt
+        .query_map([word], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, bool>(4)?,
+            ))
+        })
+        .unwrap();
+    let mut result: HashMap<(String, String), Inflections> = HashMap::new();
+    let mut from_headword: HashSet<(String, String, String)> = HashSet::new();
+    for row in rows {
+        let (language, pos, tags, form, headword) = row.unwrap();
+        let key = (language.clone(), pos.clone(), form.clone());
+        if headword {
+            from_headword.insert(key);
+        } else if from_headword.contains(&key) {
+            continue;
+        }
+        let inflections = result.entry((language, pos)).or_default();
+        match inflections.iter_mut().find(|(other, _)| *other == tags) {
+            Some((_, forms)) if forms.contains(&form) => (),
+            Some((_, forms)) => forms.push(form),
+            None => inflections.push((tags, vec![form])),
+        }
+    }
+    result
+}
+
+/// Prints inflected forms as a grid, with their tags down the left.
+fn print_inflections(inflections: Option<&Inflections>, indent: &str) {
+    let inflections = match inflections {
+        Some(inflections) => inflections,
+        None => return,
+    };
+    let label = |tags: &str| if tags.is_empty() { "form" } else { tags }.to_owned();
+    let width = inflections
+        .iter()
+        .map(|(tags, _)| label(tags).chars().count())
+        .max()
+        .unwrap_or(0);
+    for (tags, forms) in inflections {
+        let tags = format!("{:width$}", label(tags), width = width);
+        println!("{}{}  {}", indent, tags.dimmed(), forms.join(", "));
+    }
+}
+
+/// The words `word` is an inflected form of, with the language and what form it is, like
+/// ("English", "go", "past tense") for "went". What the form's own entry says goes over what the
+/// lemma's headword says, which is only used when the form has no entry in that language.
+fn get_lemmas(conn: &Connection, word: &str) -> Vec<(String, String, String)> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT language, lemma, tags FROM word_forms AS f
+             WHERE form = ?1 AND lemma != form
+             AND (name = form OR NOT EXISTS (SELECT 1 FROM words
+                  WHERE words.name = f.form AND words.language = f.language))
+             GROUP BY language, lemma, tags ORDER BY min(rowid)",
+        )
+        .unwrap();
+    stmt.query_map([word], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect()
+}
+
+/// How a form-of template reads, like "past tense of go".
+fn form_of_text(lemma: &str, inflections: &[String]) -> String {
+    let inflections: Vec<&str> = inflections
+        .iter()
+        .map(String::as_str)
+        .filter(|tags| !tags.is_empty())
+        .collect();
+    if inflections.is_empty() {
+        format!("form of {}", lemma)
+    } else {
+        format!("{} of {}", inflections.join("; "), lemma)
+    }
+}
+
+/// Leaves out the senses that only say which of `lemmas` the word is a form of, since those get
+/// defined in full.
+fn drop_forms_of(langs: &mut Definitions, lemmas: &[String]) {
+    for by_etymology in langs.values_mut() {
+        for poses in by_etymology.values_mut() {
+            for senses in poses.values_mut() {
+                senses.retain(|(_, meaning)| match &meaning.form_of {
+                    Some(lemma) => !lemmas.contains(lemma),
+                    None => true,
+                });
+            }
+            poses.retain(|_, senses| !senses.is_empty());
+        }
+        by_etymology.retain(|_, poses| !poses.is_empty());
+    }
+    langs.retain(|_, by_etymology| !by_etymology.is_empty());
+}
+
+fn get_revision(conn: &Connection, word: &str) -> Option<Revision> {
+    conn.query_row(
+        "SELECT revision_id, timestamp, sha1, contributor FROM pages
+         WHERE title = ?1 AND namespace = ?2",
+        rusqlite::params![word, NS_MAIN],
+        |row| {
+            Ok(Revision {
+                id: row.get::<_, i64>(0)? as u64,
+                timestamp: row.get(1)?,
+                sha1: row.get(2)?,
+                contributor: row.get(3)?,
+            })
+        },
+    )
+    .ok()
+}
+
+/// The URL of the main page of the wiki the database was built from, if the dump said.
+fn get_base(conn: &Connection) -> Option<String> {
+    conn.query_row("SELECT base FROM siteinfo", [], |row| row.get(0))
+        .ok()
+}
+
+/// Links to `revision` on the wiki whose main page is at `base`, like
+/// "https://en.wiktionary.org/wiki/Wiktionary:Main_Page".
+fn revision_url(base: &str, revision: &Revision) -> Option<String> {
+    let root = &base[..base.find("/wiki/")?];
+    Some(format!("{}/w/index.php?oldid={}", root, revision.id))
+}
+
+fn print_revision(revision: &Revision, base: Option<&str>) {
+    let editor = match revision.contributor {
+        Some(ref contributor) => format!(" by {}", contributor),
+        None => "".to_string(),
+    };
+    let url = match base.and_then(|base| revision_url(base, revision)) {
+        Some(url) => format!(": {}", url),
+        None => "".to_string(),
+    };
+    println!(
+        "{}",
+        format!("Last edited {}{}{}", revision.timestamp, editor, url).dimmed()
+    );
+}
+
+/// Follows redirects starting at `word`, returning where they end up if `word` is a redirect.
+fn follow_redirects(conn: &Connection, word: &str) -> Option<String> {
+    let mut target: Option<String> = None;
+    // Redirects should never chain more than once or twice, but guard against loops anyway.
+    for _ in 0..8 {
+        let next = conn
+            .query_row(
+                "SELECT target FROM redirects WHERE name = ?1",
+                [target.as_deref().unwrap_or(word)],
+                |row| row.get(0),
+            )
+            .ok();
+        match next {
+            Some(next) => target = Some(next),
+            None => break,
+        }
+    }
+    target
+}
+
 // TODO: Actually expand templates. This is very hard because Wikitext templates have a bunch of
 // functions and often call out into Lua code.
 // https://www.mediawiki.org/wiki/Help:Extension:ParserFunctions
@@ -58,7 +472,7 @@ fn expand_template(conn: &Connection, args: &[&str]) -> String {
     fn get_template_content(conn: &Connection, name: &str) -> String {
         let result = conn.query_row(
             "SELECT content FROM templates WHERE name = ?1",
-            &[&name],
+            [&name],
             |row| row.get(0),
         );
         println!("{}", name);
@@ -66,66 +480,266 @@ fn expand_template(conn: &Connection, args: &[&str]) -> String {
     }
     get_template_content(conn, args[0])
 }
-#[warn(dead_code)]
 
-// For now, we just hardcode a couple common templates.
-fn replace_template(_conn: &Connection, caps: &Captures) -> String {
-    let s = caps.get(1).unwrap().as_str();
-    let elems: Vec<&str> = s.split('|').collect();
+// For now, we just hardcode a couple common templates. `elems` is the template name followed by
+// its arguments, which have already been expanded.
+fn replace_template(_conn: &Connection, elems: &[String]) -> Option<String> {
     //match elems[0] {
     //    _ => expand_template(conn, &elems)
     //}
-    match elems[0] {
+    let arg = |i: usize| elems.get(i).cloned().unwrap_or_default();
+    Some(match elems[0].as_str() {
         "," => ",".to_owned(),
-        "ngd" | "unsupported" | "non-gloss definition" => elems[1].to_owned(),
-        "alternative form of" => format!("Alternative form of {}", elems[1]),
-        "ja-romanization of" => format!("Rōmaji transcription of {}", elems[1]),
-        "sumti" => format!("x{}", elems[1]),
-        "ja-def" => format!("{}:", elems[1]),
-        "qualifier" => format!("({})", elems[1]),
-        "lb" => format!("({})", elems[2]),
-        "m" | "l" => elems[2].to_owned(),
-        _ => caps.get(0).unwrap().as_str().to_owned(),
+        "ngd" | "unsupported" | "non-gloss definition" => arg(1),
+        "alternative form of" => format!("Alternative form of {}", arg(1)),
+        "ja-romanization of" => format!("Rōmaji transcription of {}", arg(1)),
+        "sumti" => format!("x{}", arg(1)),
+        "ja-def" => format!("{}:", arg(1)),
+        "qualifier" => format!("({})", arg(1)),
+        "lb" => format!("({})", arg(2)),
+        "m" | "l" => arg(2),
+        _ => return None,
+    })
+}
+
+/// Expands the templates we know in `nodes`, innermost first. Templates we don't know are left
+/// as wikitext, with whatever is inside them expanded.
+fn expand_templates(conn: &Connection, nodes: &[Node]) -> String {
+    let mut result = String::new();
+    for node in nodes {
+        match node {
+            Node::Template { name, args } => {
+                if let Some((lemma, inflections)) = parse_form_of(node) {
+                    result.push_str(&form_of_text(&lemma, &inflections));
+                    continue;
+                }
+                let mut elems = vec![name.trim().to_owned()];
+                elems.extend(args.iter().map(|arg| {
+                    let value = expand_templates(conn, &arg.value);
+                    match &arg.name {
+                        Some(name) => format!("{}={}", name, value),
+                        None => value,
+                    }
+                }));
+                match replace_template(conn, &elems) {
+                    Some(expanded) => result.push_str(&expanded),
+                    None => result.push_str(&format!("{{{{{}}}}}", elems.join("|"))),
+                }
+            }
+            _ => result.push_str(&to_text(std::slice::from_ref(node))),
+        }
     }
+    result
 }
 
-fn print_words<F>(
-    langs: &BTreeMap<String, BTreeMap<(String, Option<String>), Vec<String>>>,
-    mut format: F,
-) where
+fn print_examples<F>(examples: &[Example], indent: &str, format: &mut F)
+where
     F: FnMut(&str) -> String,
 {
-    let textwrap_opts = textwrap::Options::new(80)
-        .initial_indent("    ")
-        .subsequent_indent("      ");
+    // Quotations have their passage under the citation, indented a bit more.
+    let passage_indent = format!("{}  ", indent);
+    for example in examples {
+        let citation = match (&example.year, &example.source) {
+            (Some(year), Some(source)) if !source.contains(year.as_str()) => {
+                Some(format!("{}, {}", year, source))
+            }
+            (_, Some(source)) => Some(source.clone()),
+            (year, None) => year.clone(),
+        };
+        let indent = match citation {
+            Some(citation) => {
+                let textwrap_opts = textwrap::Options::new(80)
+                    .initial_indent(indent)
+                    .subsequent_indent(indent);
+                println!("{}", textwrap::fill(&citation, &textwrap_opts).dimmed());
+                &passage_indent
+            }
+            None => indent,
+        };
+        let mut text = format(&example.text).italic().to_string();
+        if let Some(translation) = &example.translation {
+            text = format!("{} ― {}", text, format(translation));
+        }
+        let textwrap_opts = textwrap::Options::new(80)
+            .initial_indent(indent)
+            .subsequent_indent(indent);
+        println!("{}", textwrap::fill(&text, &textwrap_opts));
+    }
+}
+
+/// Prints a heading, followed by the IPA that goes with it, if there is any.
+fn print_heading(heading: &str, ipa: &[&String]) {
+    if ipa.is_empty() {
+        println!("{}", heading);
+    } else {
+        let ipa: Vec<&str> = ipa.iter().map(|ipa| ipa.as_str()).collect();
+        println!("{} {}", heading, ipa.join(", "));
+    }
+}
 
-    for (lang, poses) in langs {
-        println!("{}", lang.green().bold());
-        for ((pos, gender), defns) in poses {
-            println!(
-                "  {} {}",
-                pos.white(),
-                gender.clone().unwrap_or("".to_string()).italic()
-            );
-            for defn in defns {
-                let defn = format(defn);
-                let defn = textwrap::fill(&defn, &textwrap_opts);
-                println!("{}", defn);
+/// Prints definitions. Etymologies in `details` are printed above their senses, and when a
+/// language has several, the senses are headed by their number either way. IPA goes next to the
+/// language, or next to the etymology it's for if that has a heading.
+fn print_words<F>(langs: &Definitions, details: &Details, mut format: F)
+where
+    F: FnMut(&str) -> String,
+{
+    let ipa = &details.ipa;
+    for (lang, by_etymology) in langs {
+        let numbered = by_etymology.len() > 1;
+        let language_ipa: Vec<&String> = ipa
+            .iter()
+            .filter(|((language, etymology), _)| {
+                language == lang && (etymology.is_none() || !numbered)
+            })
+            .flat_map(|(_, ipa)| ipa)
+            .collect();
+        print_heading(&lang.green().bold().to_string(), &language_ipa);
+        for (etymology, poses) in by_etymology {
+            let prose =
+                etymology.and_then(|number| details.etymologies.get(&(lang.clone(), number)));
+            let heading = match etymology {
+                Some(number) if numbered => Some(format!("Etymology {}", number)),
+                Some(_) if prose.is_some() => Some("Etymology".to_owned()),
+                _ => None,
+            };
+            if let Some(heading) = heading {
+                let heading = format!("  {}", heading.yellow());
+                match ipa.get(&(lang.clone(), *etymology)) {
+                    Some(ipa) if numbered => {
+                        print_heading(&heading, &ipa.iter().collect::<Vec<_>>())
+                    }
+                    _ => print_heading(&heading, &[]),
+                }
+            }
+            if let Some(prose) = prose {
+                let textwrap_opts = textwrap::Options::new(80)
+                    .initial_indent("    ")
+                    .subsequent_indent("    ");
+                println!("{}", textwrap::fill(&format(prose), &textwrap_opts));
             }
+            print_parts_of_speech(lang, *etymology, poses, details, &mut format);
+            let related = details.relations.get(&(lang.clone(), *etymology, None));
+            print_relations(related, "  ");
         }
     }
 
-    if langs.len() == 0usize {
+    if langs.is_empty() {
         println!("No results found.");
     }
 }
 
+fn print_parts_of_speech<F>(
+    lang: &str,
+    etymology: Option<usize>,
+    poses: &BTreeMap<(String, Option<String>), Senses>,
+    details: &Details,
+    format: &mut F,
+) where
+    F: FnMut(&str) -> String,
+{
+    let mut poses = poses.iter().peekable();
+    while let Some(((pos, gender), defns)) = poses.next() {
+        println!(
+            "  {} {}",
+            pos.white(),
+            gender.clone().unwrap_or("".to_string()).italic()
+        );
+        for (id, meaning) in defns {
+            // Like on Wiktionary, sub-senses only show their own part of the number: "1.b" is
+            // shown as "b." under sense 1.
+            let label = meaning.ordinal.rsplit('.').next().unwrap_or("");
+            let indent = " ".repeat(2 + 2 * meaning.depth);
+            let initial_indent = format!("{}{}. ", indent, label);
+            let subsequent_indent = " ".repeat(initial_indent.chars().count());
+            let textwrap_opts = textwrap::Options::new(80)
+                .initial_indent(&initial_indent)
+                .subsequent_indent(&subsequent_indent);
+            let defn = format(&meaning.definition);
+            let defn = textwrap::fill(&defn, &textwrap_opts);
+            println!("{}", defn);
+            if details.examples {
+                print_examples(&meaning.examples, &subsequent_indent, format);
+            }
+            print_relations(details.sense_relations.get(id), &subsequent_indent);
+        }
+        // Inflections and words related to the part of speech as a whole go after its last
+        // gender.
+        if poses.peek().is_none_or(|((next, _), _)| next != pos) {
+            let inflections = details.inflections.get(&(lang.to_owned(), pos.clone()));
+            print_inflections(inflections, "    ");
+            let key = (lang.to_owned(), etymology, Some(pos.clone()));
+            print_relations(details.relations.get(&key), "    ");
+        }
+    }
+}
+
+/// Looks up what's printed along with the definitions of `word`, going by the options.
+fn get_details(conn: &Connection, word: &str, matches: &Matches) -> Details {
+    let mut details = Details {
+        ipa: get_ipa(conn, word),
+        examples: matches.opt_present("e"),
+        ..Details::default()
+    };
+    if matches.opt_present("E") {
+        details.etymologies = get_etymologies(conn, word);
+    }
+    if matches.opt_present("i") {
+        details.inflections = get_inflections(conn, word);
+    }
+    let kinds: Vec<RelationKind> = if matches.opt_present("relations") {
+        RelationKind::ALL.to_vec()
+    } else {
+        let mut kinds = Vec::new();
+        if matches.opt_present("synonyms") {
+            kinds.push(RelationKind::Synonym);
+        }
+        if matches.opt_present("antonyms") {
+            kinds.push(RelationKind::Antonym);
+        }
+        kinds
+    };
+    if !kinds.is_empty() {
+        get_relations(conn, word, &kinds, &mut details);
+    }
+    details
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
     let mut opts = Options::new();
     opts.optflag("h", "help", "print this help text");
     opts.optflag("r", "raw", "don't expand wiki templates");
     opts.optopt("l", "language", "only print this language", "lang");
+    opts.optflag(
+        "e",
+        "examples",
+        "show usage examples and quotations under each sense",
+    );
+    opts.optflag("E", "etymology", "show where the word comes from");
+    opts.optflag("", "synonyms", "show synonyms");
+    opts.optflag("", "antonyms", "show antonyms");
+    opts.optflag(
+        "",
+        "relations",
+        "show all related words: synonyms, antonyms, hypernyms, derived terms and so on",
+    );
+    opts.optflag(
+        "i",
+        "inflections",
+        "show the inflected forms: plurals, cases, conjugations and so on",
+    );
+    opts.optopt(
+        "t",
+        "translate",
+        "print the translations into this language, by sense",
+        "lang",
+    );
+    opts.optflag(
+        "R",
+        "revision",
+        "show when the entry was last edited, with a link to that revision",
+    );
     let matches = opts.parse(&args[1..]).unwrap();
     if matches.opt_present("h") || matches.free.len() != 1 {
         let brief = format!("Usage: {} [options] WORD", args[0]);
@@ -133,17 +747,30 @@ fn main() {
         return;
     }
 
-    // TODO: We currently support nested templates in a very bad way. We expand templates in
-    // layers, most deeply nested first, and we do this by excluding curly braces in the regex.
-    // Should eventually use a more legit parser (nom maybe?)
-    let re_template = Regex::new(r"\{\{(?P<text>(?s:[^\{])*?)\}\}").unwrap();
-
     let mut sqlite_path = dirs::data_dir().unwrap();
     sqlite_path.push("define3");
     sqlite_path.push("define3.sqlite3");
     let conn = Connection::open(Path::new(&sqlite_path)).unwrap();
 
-    let all_langs = *get_defns_by_lang(&conn, &matches.free[0]);
+    let mut word = matches.free[0].clone();
+    let examples = matches.opt_present("e");
+    let mut all_langs = *get_defns_by_lang(&conn, &word, examples);
+    if all_langs.is_empty() {
+        if let Some(target) = follow_redirects(&conn, &word) {
+            println!("{}", format!("(redirected from {})", word).dimmed());
+            all_langs = *get_defns_by_lang(&conn, &target, examples);
+            word = target;
+        }
+    }
+    if let Some(language) = matches.opt_str("t") {
+        let code = language_code(&conn, &language);
+        let mut translations = get_translations(&conn, &word, &code);
+        if let Some(lang) = matches.opt_str("l") {
+            translations.retain(|(language, _), _| *language == lang);
+        }
+        print_translations(&translations);
+        return;
+    }
     let langs = match matches.opt_str("l") {
         None => all_langs,
         Some(lang) => {
@@ -154,21 +781,56 @@ fn main() {
             result
         }
     };
-    print_words(&langs, |s| {
-        let replace_template = |caps: &Captures| -> String { replace_template(&conn, caps) };
-        let mut result = s.to_owned();
-        if !matches.opt_present("r") {
-            loop {
-                let result_ = re_template
-                    .replace_all(&result, &replace_template)
-                    .to_string();
-                //println!("{}", result_);
-                if result == result_ {
-                    break;
-                }
-                result = result_;
+    // Inflected forms, like "went", are defined by the words they're a form of.
+    let mut lemmas: Vec<(String, Vec<(String, String)>)> = Vec::new();
+    for (language, lemma, tags) in get_lemmas(&conn, &word) {
+        if matches.opt_str("l").is_some_and(|lang| lang != language) {
+            continue;
+        }
+        match lemmas.iter_mut().find(|(other, _)| *other == lemma) {
+            Some((_, forms)) => forms.push((language, tags)),
+            None => lemmas.push((lemma, vec![(language, tags)])),
+        }
+    }
+    let lemmas: Vec<_> = lemmas
+        .into_iter()
+        .map(|(lemma, forms)| {
+            let mut defns = *get_defns_by_lang(&conn, &lemma, examples);
+            defns.retain(|language, _| forms.iter().any(|(other, _)| other == language));
+            (lemma, forms, defns)
+        })
+        .filter(|(_, _, defns)| !defns.is_empty())
+        .collect();
+    let mut langs = langs;
+    let names: Vec<String> = lemmas.iter().map(|(lemma, _, _)| lemma.clone()).collect();
+    drop_forms_of(&mut langs, &names);
+
+    let mut format = |s: &str| {
+        if matches.opt_present("r") {
+            s.to_owned()
+        } else {
+            expand_templates(&conn, &parse(s))
+        }
+    };
+    if !langs.is_empty() || lemmas.is_empty() {
+        print_words(&langs, &get_details(&conn, &word, &matches), &mut format);
+    }
+    for (lemma, forms, defns) in &lemmas {
+        let mut printed = Vec::new();
+        for (_, tags) in forms {
+            if printed.contains(&tags) {
+                continue;
             }
+            printed.push(tags);
+            let text = form_of_text(lemma, std::slice::from_ref(tags));
+            println!("{}: {}", word.bold(), text);
         }
-        result
-    });
+        print_words(defns, &get_details(&conn, lemma, &matches), &mut format);
+    }
+
+    if matches.opt_present("R") {
+        if let Some(revision) = get_revision(&conn, &word) {
+            print_revision(&revision, get_base(&conn).as_deref());
+        }
+    }
 }
@@ -0,0 +1,114 @@
+use std::collections::HashSet;
+
+/// Which language sections to read definitions from.
+pub enum LanguageFilter {
+    /// Every level 2 heading, for when we haven't seen the language data yet.
+    Any,
+    /// Only these languages, by canonical name.
+    Only(HashSet<String>),
+}
+
+impl LanguageFilter {
+    pub fn accepts(&self, name: &str) -> bool {
+        match self {
+            LanguageFilter::Any => true,
+            LanguageFilter::Only(names) => names.contains(name),
+        }
+    }
+}
+
+/// Whether a module (named without its `Module:` prefix) is one of the ones listing languages:
+/// `languages/data2`, `languages/data/3/a` and so on.
+pub fn is_language_data(module: &str) -> bool {
+    module.starts_with("languages/data")
+}
+
+/// Skips whitespace and Lua comments.
+fn skip_blank(mut src: &str) -> &str {
+    loop {
+        src = src.trim_start();
+        match src.strip_prefix("--") {
+            Some(rest) => src = rest.find('\n').map_or("", |end| &rest[end..]),
+            None => return src,
+        }
+    }
+}
+
+/// Reads a Lua string literal at the start of `src`.
+fn lua_string(src: &str) -> Option<&str> {
+    let quote = src.chars().next().filter(|&c| c == '"' || c == '\'')?;
+    let end = src[1..].find(quote)?;
+    Some(&src[1..=end])
+}
+
+/// Reads the `(code, canonical name)` pairs out of a language data module. Each language is a
+/// top-level assignment whose table starts with the name, like
+/// `m["de"] = {"German", 188, "gmw", ...}`.
+pub fn parse_language_data(src: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    for line in src.lines() {
+        // Nested tables are indented, so only unindented lines can start a language.
+        let rest = line.trim_start_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        if rest.len() == line.len() {
+            continue;
+        }
+        let code = match rest.strip_prefix('[').and_then(lua_string) {
+            Some(code) => code,
+            None => continue,
+        };
+        let rest = rest[code.len() + 3..].trim_start();
+        let rest = match rest.strip_prefix(']') {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let rest = match rest.strip_prefix('=') {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let rest = match rest.strip_prefix('{') {
+            Some(rest) => rest,
+            None => continue,
+        };
+        // The name is usually on the next line, so look past this one.
+        let offset = rest.as_ptr() as usize - src.as_ptr() as usize;
+        if let Some(name) = lua_string(skip_blank(&src[offset..])) {
+            result.push((code.to_owned(), name.to_owned()));
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_codes_and_names() {
+        let src = r#"local m = {}
+
+m["aa"] = {
+	"Afar",
+	27811,
+	"cus-eas",
+	translit = {["from"] = "x"},
+}
+
+-- m["xx"] = {"Commented out"}
+m['de'] = { -- German
+	'German', 188,
+}
+
+m["gem-pro"] = {"Proto-Germanic", 669623}
+
+return require("Module:languages").finalizeData(m, "language")
+"#;
+        assert_eq!(
+            parse_language_data(src),
+            vec![
+                ("aa".to_owned(), "Afar".to_owned()),
+                ("de".to_owned(), "German".to_owned()),
+                ("gem-pro".to_owned(), "Proto-Germanic".to_owned()),
+            ]
+        );
+    }
+}
@@ -1,24 +1,238 @@
+pub mod languages;
+pub mod parse_html;
 pub mod parse_wikitext;
 pub mod parse_xml;
+pub mod relations;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Meaning {
     pub language: String,
     pub part_of_speech: String,
     pub definition: String,
     pub gender: Option<String>,
+    /// How deeply the sense is nested: 1 for `#`, 2 for `##` and so on.
+    pub depth: usize,
+    /// The sense's number in its section the way Wiktionary shows it, like "1" or "1.a".
+    pub ordinal: String,
+    /// The index of the sense this is a sub-sense of, among the meanings of the same page.
+    pub parent: Option<usize>,
+    pub examples: Vec<Example>,
+    /// The number of the etymology section the sense is under, if it's under one.
+    pub etymology: Option<usize>,
+    /// The word the sense says this is a form of, like "cat" for "plural of cat".
+    pub form_of: Option<String>,
+}
+
+/// A usage example or a quotation illustrating a sense.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Example {
+    pub text: String,
+    pub translation: Option<String>,
+    /// Where a quotation is from, like "Herman Melville, Moby-Dick".
+    pub source: Option<String>,
+    pub year: Option<String>,
+}
+
+/// Where a word comes from, in one of its languages. Homographs like "bank" have several,
+/// numbered from 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Etymology {
+    pub language: String,
+    pub number: usize,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PronunciationKind {
+    Ipa,
+    /// The name of a recording on Wikimedia Commons, like "En-us-bank.ogg".
+    Audio,
+    /// The rhyme, without the leading hyphen, like "æŋk".
+    Rhymes,
+    /// The syllables, separated by "‧".
+    Hyphenation,
+}
+
+impl PronunciationKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PronunciationKind::Ipa => "IPA",
+            PronunciationKind::Audio => "audio",
+            PronunciationKind::Rhymes => "rhymes",
+            PronunciationKind::Hyphenation => "hyphenation",
+        }
+    }
+}
+
+/// A way a word is pronounced, in one of its languages and, if it's in one, etymologies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pronunciation {
+    pub language: String,
+    pub etymology: Option<usize>,
+    pub kind: PronunciationKind,
+    pub value: String,
+    /// The accents it's for, like "UK" or "General American".
+    pub accent: Option<String>,
+}
+
+/// A translation of a word into another language, from a translation table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Translation {
+    /// The language of the word being translated.
+    pub language: String,
+    pub part_of_speech: Option<String>,
+    /// The sense being translated, as given by `{{trans-top}}`.
+    pub gloss: Option<String>,
+    /// The language code of the translation, like "fr".
+    pub code: String,
+    pub word: String,
+    pub gender: Option<String>,
+    pub transliteration: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum RelationKind {
+    Synonym,
+    Antonym,
+    Hypernym,
+    Hyponym,
+    Meronym,
+    Holonym,
+    Troponym,
+    CoordinateTerm,
+    DerivedTerm,
+    RelatedTerm,
+}
+
+impl RelationKind {
+    pub const ALL: &'static [RelationKind] = &[
+        RelationKind::Synonym,
+        RelationKind::Antonym,
+        RelationKind::Hypernym,
+        RelationKind::Hyponym,
+        RelationKind::Meronym,
+        RelationKind::Holonym,
+        RelationKind::Troponym,
+        RelationKind::CoordinateTerm,
+        RelationKind::DerivedTerm,
+        RelationKind::RelatedTerm,
+    ];
+
+    /// The heading of the section listing them, like "Synonyms".
+    pub fn heading(&self) -> &'static str {
+        match self {
+            RelationKind::Synonym => "Synonyms",
+            RelationKind::Antonym => "Antonyms",
+            RelationKind::Hypernym => "Hypernyms",
+            RelationKind::Hyponym => "Hyponyms",
+            RelationKind::Meronym => "Meronyms",
+            RelationKind::Holonym => "Holonyms",
+            RelationKind::Troponym => "Troponyms",
+            RelationKind::CoordinateTerm => "Coordinate terms",
+            RelationKind::DerivedTerm => "Derived terms",
+            RelationKind::RelatedTerm => "Related terms",
+        }
+    }
+
+    /// The name saved in the database, like "synonym".
+    pub fn name(&self) -> String {
+        let heading = self.heading().to_lowercase();
+        heading.strip_suffix('s').unwrap_or(&heading).to_owned()
+    }
+
+    pub fn from_name(name: &str) -> Option<RelationKind> {
+        RelationKind::ALL
+            .iter()
+            .find(|kind| kind.name() == name)
+            .cloned()
+    }
+}
+
+/// A word related to another one, like a synonym.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relation {
+    /// The language of the word it's related to.
+    pub language: String,
+    pub etymology: Option<usize>,
+    pub part_of_speech: Option<String>,
+    pub kind: RelationKind,
+    pub target: String,
+    /// The index of the sense it's for among the meanings of the same page, if that's known.
+    pub sense: Option<usize>,
+    /// What the sense it's for is, like in `{{sense|financial institution}}`.
+    pub gloss: Option<String>,
+}
+
+/// An inflected form of a word, like "went" for "go".
+#[derive(Debug, Clone, PartialEq)]
+pub struct WordForm {
+    pub language: String,
+    pub part_of_speech: String,
+    /// The word it's a form of, like "go".
+    pub lemma: String,
+    pub form: String,
+    /// What makes it that form, like "third-person singular present".
+    pub tags: String,
+}
+
+/// Namespace ids that are the same on every MediaWiki site, whatever they're called locally.
+pub const NS_MAIN: i32 = 0;
+pub const NS_TEMPLATE: i32 = 10;
+pub const NS_MODULE: i32 = 828;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Namespace {
+    pub id: i32,
+    /// The localized name, e.g. "Template" or "Modèle". Empty for the main namespace.
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Revision {
+    pub id: u64,
+    /// When the revision was saved, as an ISO 8601 timestamp.
+    pub timestamp: String,
+    /// The base-36 SHA-1 of the revision's text.
+    pub sha1: String,
+    /// The username of the editor, or their IP address if they weren't logged in.
+    pub contributor: Option<String>,
 }
 
 #[derive(Debug)]
 pub struct Page {
+    /// The full title, including the namespace prefix.
     pub title: String,
+    pub namespace: Namespace,
+    /// The title this page redirects to, if it's a redirect.
+    pub redirect: Option<String>,
+    /// The latest revision of the page, which `content` is the text of.
+    pub revision: Revision,
     pub content: String,
 }
 
-#[derive(Debug)]
+impl Page {
+    /// The title without its namespace prefix, e.g. "en-noun" for "Template:en-noun".
+    pub fn name(&self) -> &str {
+        if self.namespace.name.is_empty() {
+            return &self.title;
+        }
+        self.title
+            .strip_prefix(self.namespace.name.as_str())
+            .and_then(|rest| rest.strip_prefix(':'))
+            .unwrap_or(&self.title)
+    }
+}
+
+#[derive(Debug, Default)]
 pub struct Word {
     pub name: String,
     pub meanings: Vec<Meaning>,
+    pub etymologies: Vec<Etymology>,
+    pub pronunciations: Vec<Pronunciation>,
+    pub translations: Vec<Translation>,
+    pub relations: Vec<Relation>,
+    /// Forms of this word, and the words this one is a form of.
+    pub forms: Vec<WordForm>,
 }
 
 #[derive(Debug)]
@@ -33,9 +247,16 @@ pub struct Module {
     pub src: String,
 }
 
+#[derive(Debug)]
+pub struct Redirect {
+    pub name: String,
+    pub target: String,
+}
+
 #[derive(Debug)]
 pub enum PageContent {
     Word(Word),
     Template(Template),
     Module(Module),
+    Redirect(Redirect),
 }
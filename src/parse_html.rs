@@ -0,0 +1,834 @@
+extern crate scraper;
+extern crate serde_json;
+use parse_html::scraper::{ElementRef, Html, Node, Selector};
+use parse_html::serde_json::Value;
+
+use std::collections::HashSet;
+use std::io::{self, BufRead};
+use std::path::Path;
+
+use languages::LanguageFilter;
+use parse_wikitext::WikiContext::*;
+use parse_wikitext::{find_year, inflection_tags, section, ContextStack, Section, SenseCounter};
+use parse_xml::{open_dump, ParseError, ParseErrorKind};
+use {
+    Etymology, Example, Meaning, Namespace, Page, Pronunciation, PronunciationKind, Relation,
+    RelationKind, Revision, Translation, Word, WordForm, NS_MAIN,
+};
+
+/// Elements inside a definition that aren't part of the definition itself: nested senses,
+/// examples and quotations, and the odd bit of inline CSS.
+const NOT_DEFINITION_TEXT: &[&str] = &["ol", "ul", "dl", "style", "script", "link"];
+
+fn definition_text(element: ElementRef, text: &mut String) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => text.push_str(t),
+            Node::Element(e) if NOT_DEFINITION_TEXT.contains(&e.name()) => (),
+            Node::Element(_) => definition_text(ElementRef::wrap(child).unwrap(), text),
+            _ => (),
+        }
+    }
+}
+
+/// The trimmed text of the first element matching `selector` in `element`, if there's any.
+fn select_text(element: ElementRef, selector: &str) -> Option<String> {
+    let selector = Selector::parse(selector).unwrap();
+    element
+        .select(&selector)
+        .next()
+        .map(|found| found.text().collect::<String>().trim().to_owned())
+        .filter(|text| !text.is_empty())
+}
+
+/// The usage examples (in a `<dl>`) and quotations (in a `<ul>`) under a sense's `<li>`.
+fn examples(item: ElementRef) -> Vec<Example> {
+    let mut result = Vec::new();
+    for list in item.child_elements() {
+        let name = list.value().name();
+        if name != "dl" && name != "ul" {
+            continue;
+        }
+        for entry in list.child_elements() {
+            // Synonyms and the like are listed the same way.
+            if select_text(entry, ".nyms").is_some() {
+                continue;
+            }
+            let text = select_text(entry, ".e-example, .e-quotation")
+                .or_else(|| Some(entry.text().collect::<String>().trim().to_owned()))
+                .unwrap();
+            let mut example = Example {
+                text,
+                translation: select_text(entry, ".e-translation"),
+                ..Example::default()
+            };
+            if name == "ul" {
+                example.source = select_text(entry, ".cited-source")
+                    .map(|source| source.trim_end_matches(':').trim_end().to_owned());
+                example.year = example.source.as_deref().and_then(find_year);
+            }
+            if !example.text.is_empty() {
+                result.push(example);
+            }
+        }
+    }
+    result
+}
+
+/// The words in `element` and everything in it, which are marked with their language. Their
+/// transliterations are too, so they're left out.
+fn words_in(element: ElementRef) -> Vec<String> {
+    element
+        .select(&Selector::parse("[lang]:not(.tr)").unwrap())
+        .map(|word| word.text().collect::<String>().trim().to_owned())
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+/// The synonyms and other related words listed under a sense's `<li>`, like
+/// `<span class="nyms synonym">Synonyms: <span lang="en">talk</span></span>`.
+fn sense_relations(item: ElementRef) -> Vec<(RelationKind, String)> {
+    let mut result = Vec::new();
+    let nyms = Selector::parse(".nyms").unwrap();
+    for list in item.child_elements().filter(|e| e.value().name() == "dl") {
+        for found in list.select(&nyms) {
+            let classes: Vec<&str> = found.value().classes().collect();
+            let kind = RelationKind::ALL
+                .iter()
+                .find(|kind| classes.contains(&kind.name().replace(' ', "-").as_str()));
+            if let Some(kind) = kind {
+                result.extend(words_in(found).into_iter().map(|word| (*kind, word)));
+            }
+        }
+    }
+    result
+}
+
+/// The pronunciations on a line of a rendered Pronunciation section. The accents on the line, in
+/// `(UK)`-style labels, go for everything on it.
+fn html_pronunciations(
+    item: ElementRef,
+    language: &str,
+    etymology: Option<usize>,
+) -> Vec<Pronunciation> {
+    let mut result = Vec::new();
+    let selector = |selector: &str| Selector::parse(selector).unwrap();
+    let texts = |selector: Selector| {
+        item.select(&selector)
+            .map(|found| found.text().collect::<String>().trim().to_owned())
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<_>>()
+    };
+    let accents = texts(selector(".ib-content, .accent-qualifier"));
+    let accent = if accents.is_empty() {
+        None
+    } else {
+        Some(accents.join(", "))
+    };
+    let mut push = |kind: PronunciationKind, value: String| {
+        result.push(Pronunciation {
+            language: language.to_owned(),
+            etymology,
+            kind,
+            value,
+            accent: accent.clone(),
+        })
+    };
+
+    // Rhymes are in IPA too, but they're links to the rhymes page.
+    let rhymes = selector("a[title^=\"Rhymes:\"]");
+    for ipa in item.select(&selector(".IPA")) {
+        let in_rhymes = ipa
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .any(|ancestor| rhymes.matches(&ancestor));
+        let ipa = ipa.text().collect::<String>();
+        if !in_rhymes && !ipa.trim().is_empty() {
+            push(PronunciationKind::Ipa, ipa.trim().to_owned());
+        }
+    }
+    for audio in item.select(&selector("[resource]")) {
+        let resource = audio.value().attr("resource").unwrap();
+        if let Some(file) = resource.strip_prefix("./File:") {
+            push(PronunciationKind::Audio, file.to_owned());
+        }
+    }
+    for rhyme in item.select(&rhymes) {
+        let rhyme = rhyme.text().collect::<String>();
+        push(
+            PronunciationKind::Rhymes,
+            rhyme.trim().trim_start_matches('-').to_owned(),
+        );
+    }
+    let text = item.text().collect::<String>();
+    if let Some(hyphenation) = text.trim().strip_prefix("Hyphenation:") {
+        for syllables in hyphenation.split(',') {
+            push(PronunciationKind::Hyphenation, syllables.trim().to_owned());
+        }
+    }
+    result
+}
+
+/// The translations on a line of a rendered translation table, like
+/// `French: <span lang="fr">banque</span> <span class="gender">f</span>`. Lines for dialects are
+/// lists of their own inside the line, so only what's directly in it is read.
+fn html_translations(
+    item: ElementRef,
+    gloss: &Option<String>,
+    language: &str,
+    part_of_speech: Option<&String>,
+) -> Vec<Translation> {
+    let mut result: Vec<Translation> = Vec::new();
+    for element in item.child_elements() {
+        let text = element.text().collect::<String>().trim().to_owned();
+        let classes: Vec<&str> = element.value().classes().collect();
+        if classes.contains(&"tr") {
+            if let Some(translation) = result.last_mut() {
+                translation.transliteration = Some(text);
+            }
+        } else if classes.contains(&"gender") {
+            if let Some(translation) = result.last_mut() {
+                translation.gender = Some(text);
+            }
+        } else if let Some(code) = element.value().attr("lang") {
+            if !text.is_empty() {
+                result.push(Translation {
+                    language: language.to_owned(),
+                    part_of_speech: part_of_speech.cloned(),
+                    gloss: gloss.clone(),
+                    code: code.to_owned(),
+                    word: text,
+                    gender: None,
+                    transliteration: None,
+                });
+            }
+        }
+    }
+    result
+}
+
+/// The inflected forms in a headword line or an inflection table, with their tags. MediaWiki
+/// puts those in a class, like `gen|s-form-of`.
+fn html_forms(element: ElementRef) -> Vec<(String, String)> {
+    let selector = Selector::parse(".form-of").unwrap();
+    element
+        .select(&selector)
+        .filter_map(|form| {
+            let tags = form
+                .value()
+                .classes()
+                .find_map(|class| class.strip_suffix("-form-of"))?;
+            let text = form.text().collect::<String>().trim().to_owned();
+            if text.is_empty() || text == "—" {
+                return None;
+            }
+            Some((text, inflection_tags(&tags.split('|').collect::<Vec<_>>())))
+        })
+        .collect()
+}
+
+/// What a form-of definition in a sense's `<li>`, like "plural of chat", says: the word it's a
+/// form of, and which form.
+fn html_form_of(item: ElementRef) -> Option<(String, String)> {
+    let selector = Selector::parse(".form-of-definition").unwrap();
+    // Not the ones in its sub-senses.
+    let definition = item.select(&selector).find(|definition| {
+        definition
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .find(|ancestor| ancestor.value().name() == "li")
+            .map(|li| li.id())
+            == Some(item.id())
+    })?;
+    let lemma = select_text(definition, ".form-of-definition-link")?;
+    let text = definition.text().collect::<String>();
+    let tags = text
+        .split(lemma.as_str())
+        .next()
+        .unwrap()
+        .trim()
+        .to_lowercase();
+    let tags = tags.strip_suffix("of").unwrap_or(&tags).trim();
+    // {{inflection of}} lists the inflections after the word, when there are several.
+    let tags = if tags == "inflection" { "" } else { tags };
+    Some((lemma, tags.to_owned()))
+}
+
+fn inside_list_item(element: &ElementRef) -> bool {
+    element
+        .ancestors()
+        .filter_map(ElementRef::wrap)
+        .any(|ancestor| ancestor.value().name() == "li")
+}
+
+/// Pulls the definitions and etymologies out of a rendered Wiktionary page. Templates have
+/// already been expanded by MediaWiki, so what we get is what a reader on the site would see.
+/// Like with `parse_wikitext`, `title` is the word's name.
+pub fn parse_html(
+    title: &str,
+    html: &str,
+    languages: &LanguageFilter,
+    parts_of_speech: &HashSet<&str>,
+) -> Word {
+    let mut result: Vec<Meaning> = Vec::new();
+    let mut etymologies: Vec<Etymology> = Vec::new();
+    let mut pronunciations: Vec<Pronunciation> = Vec::new();
+    let mut translations: Vec<Translation> = Vec::new();
+    let mut relations: Vec<Relation> = Vec::new();
+    let mut forms: Vec<WordForm> = Vec::new();
+    let mut current_section: Option<Section> = None;
+    let mut context_stack: ContextStack = ContextStack::new();
+    let mut senses = SenseCounter::default();
+
+    let document = Html::parse_document(html);
+    let selector =
+        Selector::parse("h1, h2, h3, h4, h5, h6, .headword-line, p, ol, ul, table").unwrap();
+    let gender_selector = Selector::parse(".gender").unwrap();
+
+    for element in document.select(&selector) {
+        let text = element.text().collect::<String>().trim().to_owned();
+        let context = match element.value().name() {
+            "h1" => Some(Heading1(text)),
+            "h2" => Some(Heading2(text)),
+            "h3" => Some(Heading3(text)),
+            "h4" => Some(Heading4(text)),
+            "h5" => Some(Heading5(text)),
+            "h6" => Some(Heading6(text)),
+            _ => None,
+        };
+        if let Some(context) = context {
+            let heading = context.text().clone();
+            context_stack.apply(context, languages, parts_of_speech);
+            senses.reset();
+            current_section = context_stack
+                .language
+                .as_ref()
+                .and_then(|_| section(&heading));
+            if let Some(Section::Etymology) = current_section {
+                etymologies.push(Etymology {
+                    language: context_stack.language.clone().unwrap(),
+                    number: context_stack.etymology.unwrap(),
+                    text: String::new(),
+                });
+            }
+        } else if element.value().name() == "ul" {
+            if current_section == Some(Section::Pronunciation) && !inside_list_item(&element) {
+                for item in element.child_elements() {
+                    pronunciations.extend(html_pronunciations(
+                        item,
+                        context_stack.language.as_ref().unwrap(),
+                        context_stack.etymology,
+                    ));
+                }
+            }
+            if let Some(Section::Relations(kind)) = current_section {
+                if !inside_list_item(&element) {
+                    relations.extend(words_in(element).into_iter().map(|target| Relation {
+                        language: context_stack.language.clone().unwrap(),
+                        etymology: context_stack.etymology,
+                        part_of_speech: context_stack.part_of_speech.clone(),
+                        kind,
+                        target,
+                        sense: None,
+                        gloss: None,
+                    }));
+                }
+            }
+            if current_section == Some(Section::Translations) && !inside_list_item(&element) {
+                // Translation tables say which sense they're for in an attribute.
+                let gloss = element
+                    .ancestors()
+                    .filter_map(ElementRef::wrap)
+                    .find_map(|ancestor| ancestor.value().attr("data-gloss"))
+                    .map(|gloss| gloss.trim().to_owned())
+                    .filter(|gloss| !gloss.is_empty());
+                for item in element.select(&Selector::parse("li").unwrap()) {
+                    translations.extend(html_translations(
+                        item,
+                        &gloss,
+                        context_stack.language.as_ref().unwrap(),
+                        context_stack.part_of_speech.as_ref(),
+                    ));
+                }
+            }
+        } else if element.value().name() == "p" {
+            if current_section == Some(Section::Etymology) {
+                let etymology = etymologies.last_mut().unwrap();
+                etymology
+                    .text
+                    .push_str(element.text().collect::<String>().trim());
+                etymology.text.push('\n');
+            }
+        } else if element.value().name() != "ol" {
+            if element.value().name() != "table" {
+                context_stack.gender = element
+                    .select(&gender_selector)
+                    .next()
+                    .map(|gender| gender.text().collect::<String>().trim().to_owned());
+            }
+            if let (Some(language), Some(part_of_speech)) =
+                (&context_stack.language, &context_stack.part_of_speech)
+            {
+                if !inside_list_item(&element) {
+                    forms.extend(
+                        html_forms(element)
+                            .into_iter()
+                            .map(|(form, tags)| WordForm {
+                                language: language.clone(),
+                                part_of_speech: part_of_speech.clone(),
+                                lemma: title.to_owned(),
+                                form,
+                                tags,
+                            }),
+                    );
+                }
+            }
+        } else if !inside_list_item(&element) {
+            let (language, part_of_speech) =
+                match (&context_stack.language, &context_stack.part_of_speech) {
+                    (Some(language), Some(part_of_speech)) => (language, part_of_speech),
+                    _ => continue,
+                };
+            let meaning = Meaning {
+                language: language.clone(),
+                part_of_speech: part_of_speech.clone(),
+                gender: context_stack.gender.clone(),
+                definition: String::new(),
+                depth: 1,
+                ordinal: String::new(),
+                parent: None,
+                examples: Vec::new(),
+                etymology: context_stack.etymology,
+                form_of: None,
+            };
+            push_senses(
+                element,
+                &meaning,
+                &mut senses,
+                &mut result,
+                &mut relations,
+                &mut forms,
+                title,
+            );
+        }
+    }
+    for etymology in &mut etymologies {
+        etymology.text = etymology.text.trim().to_owned();
+    }
+    etymologies.retain(|etymology| !etymology.text.is_empty());
+    Word {
+        name: title.to_owned(),
+        meanings: result,
+        etymologies,
+        pronunciations,
+        translations,
+        relations,
+        forms,
+    }
+}
+
+/// Adds the senses in a list, and the sub-senses in the lists inside them, at the depth and with
+/// the language and part of speech of `meaning`. Senses like "plural of chat" make `title` a
+/// form of that word.
+fn push_senses(
+    list: ElementRef,
+    meaning: &Meaning,
+    senses: &mut SenseCounter,
+    result: &mut Vec<Meaning>,
+    relations: &mut Vec<Relation>,
+    forms: &mut Vec<WordForm>,
+    title: &str,
+) {
+    for item in list.child_elements().filter(|e| e.value().name() == "li") {
+        let mut definition = String::new();
+        definition_text(item, &mut definition);
+        let definition = definition.trim();
+        if !definition.is_empty() {
+            let form_of = html_form_of(item);
+            let (ordinal, parent) = senses.next(meaning.depth, Some(result.len()));
+            result.push(Meaning {
+                language: meaning.language.clone(),
+                part_of_speech: meaning.part_of_speech.clone(),
+                gender: meaning.gender.clone(),
+                definition: definition.to_owned(),
+                depth: meaning.depth,
+                ordinal,
+                parent,
+                examples: examples(item),
+                etymology: meaning.etymology,
+                form_of: form_of.as_ref().map(|(lemma, _)| lemma.clone()),
+            });
+            for (kind, target) in sense_relations(item) {
+                relations.push(Relation {
+                    language: meaning.language.clone(),
+                    etymology: meaning.etymology,
+                    part_of_speech: Some(meaning.part_of_speech.clone()),
+                    kind,
+                    target,
+                    sense: Some(result.len() - 1),
+                    gloss: None,
+                });
+            }
+            if let Some((lemma, tags)) = form_of {
+                forms.push(WordForm {
+                    language: meaning.language.clone(),
+                    part_of_speech: meaning.part_of_speech.clone(),
+                    lemma,
+                    form: title.to_owned(),
+                    tags,
+                });
+            }
+        }
+        let sub_senses = Meaning {
+            language: meaning.language.clone(),
+            part_of_speech: meaning.part_of_speech.clone(),
+            gender: meaning.gender.clone(),
+            definition: String::new(),
+            depth: meaning.depth + 1,
+            ordinal: String::new(),
+            parent: None,
+            examples: Vec::new(),
+            etymology: meaning.etymology,
+            form_of: None,
+        };
+        for list in item.child_elements().filter(|e| e.value().name() == "ol") {
+            push_senses(list, &sub_senses, senses, result, relations, forms, title);
+        }
+    }
+}
+
+fn parse_article(line: &str) -> Result<Page, (io::Error, Option<String>)> {
+    let article: Value = serde_json::from_str(line).map_err(|e| (e.into(), None))?;
+    let title = article["name"].as_str().map(|title| title.to_owned());
+    let html = article["article_body"]["html"].as_str().ok_or_else(|| {
+        let e = io::Error::new(io::ErrorKind::InvalidData, "article has no HTML body");
+        (e, title.clone())
+    })?;
+    let title = title.unwrap_or_default();
+    // Enterprise dumps don't list namespace names, so they're taken from the title.
+    let id = article["namespace"]["identifier"].as_i64().unwrap_or(0) as i32;
+    let name = match title.split_once(':') {
+        Some((prefix, _)) if id != NS_MAIN => prefix.to_owned(),
+        _ => String::new(),
+    };
+    let namespace = Namespace { id, name };
+    let revision = Revision {
+        id: article["version"]["identifier"].as_u64().unwrap_or(0),
+        timestamp: article["date_modified"].as_str().unwrap_or("").to_owned(),
+        sha1: String::new(),
+        contributor: article["version"]["editor"]["name"]
+            .as_str()
+            .map(|name| name.to_owned()),
+    };
+    Ok(Page {
+        title,
+        namespace,
+        redirect: None,
+        revision,
+        content: html.to_owned(),
+    })
+}
+
+/// Calls `f` on every article in a Wikimedia Enterprise HTML dump, which has one JSON object per
+/// line. The pages' `content` is their rendered HTML, for `parse_html`. Errors are handled the
+/// same way as in `parse_xml::for_pages`.
+pub fn for_pages_in<R, F, E>(input: R, mut f: F, mut on_error: E) -> Result<(), ParseError>
+where
+    R: BufRead,
+    F: FnMut(Page),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    let mut position: u64 = 0;
+    for line in input.lines() {
+        let line = line.map_err(|e| ParseError {
+            position,
+            ..ParseError::from(e)
+        })?;
+        if !line.trim().is_empty() {
+            match parse_article(&line) {
+                Ok(page) => f(page),
+                Err((e, title)) => on_error(ParseError {
+                    kind: ParseErrorKind::Io(e),
+                    position,
+                    block: None,
+                    title,
+                })?,
+            }
+        }
+        position += line.len() as u64 + 1;
+    }
+    Ok(())
+}
+
+/// Like `parse_xml::for_pages`, but for an Enterprise HTML dump that's been extracted from its
+/// tarball. The file can still be compressed.
+pub fn for_pages<F, E>(filename: &str, f: F, on_error: E) -> Result<(), ParseError>
+where
+    F: FnMut(Page),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    for_pages_in(open_dump(Path::new(filename))?, f, on_error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The first element matching `selector` in a bit of rendered HTML.
+    fn first<'a>(html: &'a Html, selector: &str) -> ElementRef<'a> {
+        html.select(&Selector::parse(selector).unwrap())
+            .next()
+            .unwrap()
+    }
+
+    #[test]
+    fn pronunciations_from_rendered_lines() {
+        let pronunciations = |line: &str| {
+            let html = Html::parse_fragment(&format!("<ul>{}</ul>", line));
+            html_pronunciations(first(&html, "li"), "English", Some(1))
+                .into_iter()
+                .map(|p| (p.kind, p.value, p.accent))
+                .collect::<Vec<_>>()
+        };
+        // From "bank", as rendered by {{a}}, {{IPA}}, {{audio}}, {{rhymes}} and {{hyph}}.
+        assert_eq!(
+            pronunciations(
+                "<li><span class=\"usage-label-accent\"><span class=\"ib-brac\">(</span>\
+                 <span class=\"ib-content\"><a rel=\"mw:WikiLink\" \
+                 href=\"./w:Received_Pronunciation\">Received Pronunciation</a></span>\
+                 <span class=\"ib-brac\">)</span></span> <a rel=\"mw:WikiLink\" \
+                 href=\"./Wiktionary:International_Phonetic_Alphabet\">IPA</a><sup>(<a \
+                 rel=\"mw:WikiLink\" href=\"./Appendix:English_pronunciation\">key</a>)</sup>: \
+                 <span class=\"IPA\">/bæŋk/</span></li>"
+            ),
+            vec![(
+                PronunciationKind::Ipa,
+                "/bæŋk/".to_owned(),
+                Some("Received Pronunciation".to_owned())
+            )]
+        );
+        assert_eq!(
+            pronunciations(
+                "<li>Audio <span class=\"ib-brac\">(</span><span class=\"ib-content\">US\
+                 </span><span class=\"ib-brac\">)</span>: <span typeof=\"mw:File\">\
+                 <audio resource=\"./File:En-us-bank.ogg\" controls=\"\"></audio></span></li>"
+            ),
+            vec![(
+                PronunciationKind::Audio,
+                "En-us-bank.ogg".to_owned(),
+                Some("US".to_owned())
+            )]
+        );
+        assert_eq!(
+            pronunciations(
+                "<li><a rel=\"mw:WikiLink\" href=\"./Appendix:Rhymes\" \
+                 title=\"Appendix:Rhymes\">Rhymes</a>: <a rel=\"mw:WikiLink\" \
+                 href=\"./Rhymes:English/æŋk\" title=\"Rhymes:English/æŋk\">\
+                 <span class=\"IPA\">-æŋk</span></a></li>"
+            ),
+            vec![(PronunciationKind::Rhymes, "æŋk".to_owned(), None)]
+        );
+        assert_eq!(
+            pronunciations("<li>Hyphenation: bank</li>"),
+            vec![(PronunciationKind::Hyphenation, "bank".to_owned(), None)]
+        );
+    }
+
+    #[test]
+    fn translations_from_rendered_lines() {
+        let translations = |line: &str| {
+            let html = Html::parse_fragment(&format!("<ul>{}</ul>", line));
+            let gloss = Some("financial institution".to_owned());
+            html_translations(first(&html, "li"), &gloss, "English", None)
+                .into_iter()
+                .map(|t| (t.code, t.word, t.gender, t.transliteration))
+                .collect::<Vec<_>>()
+        };
+        // From "bank", as rendered by {{t+}}.
+        assert_eq!(
+            translations(
+                "<li>French: <span class=\"Latn\" lang=\"fr\"><a rel=\"mw:WikiLink\" \
+                 href=\"./banque#French\" title=\"banque\">banque</a></span>&nbsp;\
+                 <span class=\"gender\"><abbr title=\"feminine gender\">f</abbr></span>\
+                 <span class=\"tpos\">&nbsp;<sup>(<a href=\"https://fr.wiktionary.org/wiki/\
+                 banque\" class=\"extiw\">fr</a>)</sup></span></li>"
+            ),
+            vec![(
+                "fr".to_owned(),
+                "banque".to_owned(),
+                Some("f".to_owned()),
+                None
+            )]
+        );
+        assert_eq!(
+            translations(
+                "<li>Russian: <span class=\"Cyrl\" lang=\"ru\"><a rel=\"mw:WikiLink\" \
+                 href=\"./банк#Russian\" title=\"банк\">банк</a></span>&nbsp;\
+                 <span class=\"gender\"><abbr title=\"masculine gender\">m</abbr></span> \
+                 <span class=\"mention-gloss-paren annotation-paren\">(</span>\
+                 <span lang=\"ru-Latn\" class=\"tr Latn\">bank</span>\
+                 <span class=\"mention-gloss-paren annotation-paren\">)</span>, \
+                 <span class=\"Cyrl\" lang=\"ru\"><a rel=\"mw:WikiLink\" \
+                 href=\"./банчок#Russian\" title=\"банчок\">банчок</a></span></li>"
+            ),
+            vec![
+                (
+                    "ru".to_owned(),
+                    "банк".to_owned(),
+                    Some("m".to_owned()),
+                    Some("bank".to_owned())
+                ),
+                ("ru".to_owned(), "банчок".to_owned(), None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn relations_from_rendered_senses() {
+        // From "bank", as rendered by {{syn}} and {{ant}} under a sense.
+        let html = Html::parse_fragment(
+            "<ol><li>An <a rel=\"mw:WikiLink\" href=\"./institution\">institution</a> where \
+             one can place and borrow money.\
+             <dl><dd><span class=\"nyms synonym\"><span class=\"defdate\">Synonyms:</span> \
+             <span class=\"Latn\" lang=\"en\"><a rel=\"mw:WikiLink\" href=\"./bankhouse\">\
+             bankhouse</a></span>, <span class=\"Latn\" lang=\"en\"><a rel=\"mw:WikiLink\" \
+             href=\"./depository\">depository</a></span></span></dd>\
+             <dd><span class=\"nyms antonym\"><span class=\"defdate\">Antonym:</span> \
+             <span class=\"Latn\" lang=\"en\"><a rel=\"mw:WikiLink\" href=\"./mattress\">\
+             mattress</a></span></span></dd></dl></li></ol>",
+        );
+        assert_eq!(
+            sense_relations(first(&html, "li")),
+            vec![
+                (RelationKind::Synonym, "bankhouse".to_owned()),
+                (RelationKind::Synonym, "depository".to_owned()),
+                (RelationKind::Antonym, "mattress".to_owned()),
+            ]
+        );
+
+        // From a Synonyms section, with a transliteration that isn't a word of its own.
+        let html = Html::parse_fragment(
+            "<ul><li><span class=\"Cyrl\" lang=\"ru\"><a rel=\"mw:WikiLink\" \
+             href=\"./банк#Russian\">банк</a></span> <span class=\"mention-gloss-paren\">(\
+             </span><span lang=\"ru-Latn\" class=\"tr Latn\">bank</span>\
+             <span class=\"mention-gloss-paren\">)</span></li></ul>",
+        );
+        assert_eq!(words_in(first(&html, "ul")), vec!["банк"]);
+    }
+
+    #[test]
+    fn forms_from_rendered_headwords_and_senses() {
+        let forms = |html: &str, selector: &str| {
+            let html = Html::parse_fragment(html);
+            let forms = html_forms(first(&html, selector));
+            forms
+                .into_iter()
+                .map(|(form, tags)| format!("{}: {}", tags, form))
+                .collect::<Vec<_>>()
+        };
+        // From "Hund", as rendered by {{de-noun}}.
+        assert_eq!(
+            forms(
+                "<p><span class=\"headword-line\"><strong class=\"Latn headword\" lang=\"de\">\
+                 Hund</strong>&nbsp;<span class=\"gender\"><abbr title=\"masculine gender\">m\
+                 </abbr></span> (<i>strong</i>, <i>genitive</i> <b class=\"Latn form-of \
+                 lang-de gen|s-form-of\" lang=\"de\"><a rel=\"mw:WikiLink\" href=\"./Hundes\">\
+                 Hundes</a></b> <i>or</i> <b class=\"Latn form-of lang-de gen|s-form-of\" \
+                 lang=\"de\"><a rel=\"mw:WikiLink\" href=\"./Hunds\">Hunds</a></b>, \
+                 <i>plural</i> <b class=\"Latn form-of lang-de p-form-of\" lang=\"de\">\
+                 <a rel=\"mw:WikiLink\" href=\"./Hunde\">Hunde</a></b>)</span></p>",
+                ".headword-line"
+            ),
+            vec![
+                "genitive singular: Hundes",
+                "genitive singular: Hunds",
+                "plural: Hunde",
+            ]
+        );
+        // And by {{de-ndecl}}, in a table with a blank cell.
+        assert_eq!(
+            forms(
+                "<table class=\"inflection-table\"><tbody><tr><th>dative</th>\
+                 <td><span class=\"Latn form-of lang-de dat|s-form-of\" lang=\"de\">\
+                 <a rel=\"mw:WikiLink\" href=\"./Hund\">Hund</a></span></td>\
+                 <td><span class=\"Latn form-of lang-de dat|p-form-of\" lang=\"de\">\
+                 <a rel=\"mw:WikiLink\" href=\"./Hunden\">Hunden</a></span></td></tr>\
+                 <tr><th>vocative</th><td><span class=\"Latn form-of lang-de voc|s-form-of\" \
+                 lang=\"de\">—</span></td></tr></tbody></table>",
+                "table"
+            ),
+            vec!["dative singular: Hund", "dative plural: Hunden"]
+        );
+
+        // From "Hunde", as rendered by {{inflection of}}, but not from its sub-sense.
+        let html = Html::parse_fragment(
+            "<ol><li><span class=\"form-of-definition use-with-mention\">\
+             <a rel=\"mw:WikiLink\" href=\"./Appendix:Glossary#nominative_case\">nominative\
+             </a>/<a rel=\"mw:WikiLink\" href=\"./Appendix:Glossary#accusative_case\">\
+             accusative</a> <a rel=\"mw:WikiLink\" href=\"./Appendix:Glossary#plural_number\">\
+             plural</a> of <span class=\"form-of-definition-link\"><i class=\"Latn mention\" \
+             lang=\"de\"><a rel=\"mw:WikiLink\" href=\"./Hund#German\">Hund</a></i></span>\
+             </span><ol><li><span class=\"form-of-definition\">plural of \
+             <span class=\"form-of-definition-link\">Hündin</span></span></li></ol></li></ol>",
+        );
+        assert_eq!(
+            html_form_of(first(&html, "li")),
+            Some(("Hund".to_owned(), "nominative/accusative plural".to_owned()))
+        );
+    }
+    #[test]
+    fn articles_are_read_by_section() {
+        // Trimmed from "Hund" in an Enterprise HTML dump.
+        let html = "<section data-mw-section-id=\"1\"><h2 id=\"German\">German</h2>\
+            <section data-mw-section-id=\"2\"><h3 id=\"Noun\">Noun</h3>\
+            <p><span class=\"headword-line\"><strong class=\"Latn headword\" lang=\"de\">Hund\
+            </strong>&nbsp;<span class=\"gender\"><abbr title=\"masculine gender\">m</abbr>\
+            </span> (<i>genitive</i> <b class=\"Latn form-of lang-de gen|s-form-of\" \
+            lang=\"de\"><a rel=\"mw:WikiLink\" href=\"./Hundes\">Hundes</a></b>, \
+            <i>plural</i> <b class=\"Latn form-of lang-de p-form-of\" lang=\"de\">\
+            <a rel=\"mw:WikiLink\" href=\"./Hunde\">Hunde</a></b>)</span></p>\
+            <ol><li><a rel=\"mw:WikiLink\" href=\"./dog\">dog</a>\
+            <ol><li><span class=\"usage-label-sense\"><span class=\"ib-brac\">(</span>\
+            <span class=\"ib-content\">hunting</span><span class=\"ib-brac\">)</span></span> \
+            <a rel=\"mw:WikiLink\" href=\"./hound\">hound</a></li></ol></li>\
+            <li><a rel=\"mw:WikiLink\" href=\"./bastard\">bastard</a></li></ol>\
+            </section></section>";
+        let word = parse_html(
+            "Hund",
+            html,
+            &LanguageFilter::Any,
+            &["Noun"].iter().cloned().collect(),
+        );
+        let meanings: Vec<_> = word
+            .meanings
+            .iter()
+            .map(|m| {
+                (
+                    m.language.as_str(),
+                    m.part_of_speech.as_str(),
+                    m.gender.as_deref(),
+                    m.definition.as_str(),
+                    m.ordinal.as_str(),
+                    m.parent,
+                )
+            })
+            .collect();
+        assert_eq!(
+            meanings,
+            vec![
+                ("German", "Noun", Some("m"), "dog", "1", None),
+                (
+                    "German",
+                    "Noun",
+                    Some("m"),
+                    "(hunting) hound",
+                    "1.a",
+                    Some(0)
+                ),
+                ("German", "Noun", Some("m"), "bastard", "2", None),
+            ]
+        );
+        let forms: Vec<_> = word.forms.iter().map(|f| f.form.as_str()).collect();
+        assert_eq!(forms, vec!["Hundes", "Hunde"]);
+    }
+}
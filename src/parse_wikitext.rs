@@ -1,6 +1,656 @@
-use std::collections::HashSet;
+extern crate nom;
+use parse_wikitext::nom::bytes::complete::{tag, take_until, take_while, take_while1};
+use parse_wikitext::nom::error::{Error, ErrorKind};
+use parse_wikitext::nom::IResult;
 
-use Meaning;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use languages::LanguageFilter;
+use {
+    Etymology, Example, Meaning, Pronunciation, PronunciationKind, Relation, RelationKind,
+    Translation, Word, WordForm,
+};
+
+/// A piece of wikitext. Parsing keeps enough of the source to write it back out with
+/// `to_wikitext`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Text(String),
+    /// The end of a line outside of any template or tag.
+    Newline,
+    /// `== content ==`. Whatever is around the `=` signs stays in `content`.
+    Heading {
+        level: usize,
+        content: Vec<Node>,
+    },
+    /// A line starting with `#`, `*`, `:` or `;`. `marker` is all of them, like `#:`.
+    ListItem {
+        marker: String,
+        content: Vec<Node>,
+    },
+    /// `{{name|arg|key=value}}`. The name isn't trimmed; use `Node::template_name`.
+    Template {
+        name: String,
+        args: Vec<Argument>,
+    },
+    /// `[[target]]` or `[[target|text]]`.
+    Link {
+        target: String,
+        text: Option<Vec<Node>>,
+    },
+    Bold(Vec<Node>),
+    Italic(Vec<Node>),
+    /// An HTML or extension tag like `<sup>` or `<ref name="x">`. `content` is `None` for tags
+    /// that don't have a closing tag.
+    Tag {
+        name: String,
+        attributes: String,
+        content: Option<Vec<Node>>,
+    },
+    Comment(String),
+}
+
+/// A template argument. `name` is only there for named arguments like `g=m`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Argument {
+    pub name: Option<String>,
+    pub value: Vec<Node>,
+}
+
+impl Node {
+    /// The name of a template, without surrounding whitespace.
+    pub fn template_name(&self) -> Option<&str> {
+        match self {
+            Node::Template { name, .. } => Some(name.trim()),
+            _ => None,
+        }
+    }
+
+    /// The nodes directly inside this one, including template arguments.
+    pub fn children(&self) -> Vec<&Vec<Node>> {
+        match self {
+            Node::Heading { content, .. } | Node::ListItem { content, .. } => vec![content],
+            Node::Template { args, .. } => args.iter().map(|arg| &arg.value).collect(),
+            Node::Link { text, .. } => text.iter().collect(),
+            Node::Bold(content) | Node::Italic(content) => vec![content],
+            Node::Tag { content, .. } => content.iter().collect(),
+            Node::Text(_) | Node::Newline | Node::Comment(_) => Vec::new(),
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut Vec<Node>> {
+        match self {
+            Node::Heading { content, .. } | Node::ListItem { content, .. } => vec![content],
+            Node::Template { args, .. } => args.iter_mut().map(|arg| &mut arg.value).collect(),
+            Node::Link { text, .. } => text.iter_mut().collect(),
+            Node::Bold(content) | Node::Italic(content) => vec![content],
+            Node::Tag { content, .. } => content.iter_mut().collect(),
+            Node::Text(_) | Node::Newline | Node::Comment(_) => Vec::new(),
+        }
+    }
+}
+
+impl Argument {
+    /// The argument's name, or `None` if it is positional.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| name.trim())
+    }
+
+    pub fn to_wikitext(&self) -> String {
+        match &self.name {
+            Some(name) => format!("{}={}", name, to_wikitext(&self.value)),
+            None => to_wikitext(&self.value),
+        }
+    }
+}
+
+/// Calls `f` on every node in `nodes` and everything inside them, parents first.
+pub fn walk<'a, F: FnMut(&'a Node)>(nodes: &'a [Node], f: &mut F) {
+    for node in nodes {
+        f(node);
+        for children in node.children() {
+            walk(children, f);
+        }
+    }
+}
+
+/// Removes every node, however deeply nested, for which `f` returns false.
+pub fn retain<F: FnMut(&Node) -> bool>(nodes: &mut Vec<Node>, f: &mut F) {
+    nodes.retain(|node| f(node));
+    for node in nodes.iter_mut() {
+        for children in node.children_mut() {
+            retain(children, f);
+        }
+    }
+}
+
+/// Writes nodes back out as wikitext.
+pub fn to_wikitext(nodes: &[Node]) -> String {
+    let mut result = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => result.push_str(text),
+            Node::Newline => result.push('\n'),
+            Node::Heading { level, content } => {
+                let equals = "=".repeat(*level);
+                result.push_str(&format!("{}{}{}", equals, to_wikitext(content), equals));
+            }
+            Node::ListItem { marker, content } => {
+                result.push_str(marker);
+                result.push_str(&to_wikitext(content));
+            }
+            Node::Template { name, args } => {
+                result.push_str("{{");
+                result.push_str(name);
+                for arg in args {
+                    result.push('|');
+                    result.push_str(&arg.to_wikitext());
+                }
+                result.push_str("}}");
+            }
+            Node::Link { target, text } => {
+                result.push_str("[[");
+                result.push_str(target);
+                if let Some(text) = text {
+                    result.push('|');
+                    result.push_str(&to_wikitext(text));
+                }
+                result.push_str("]]");
+            }
+            Node::Bold(content) => result.push_str(&format!("'''{}'''", to_wikitext(content))),
+            Node::Italic(content) => result.push_str(&format!("''{}''", to_wikitext(content))),
+            Node::Tag {
+                name,
+                attributes,
+                content,
+            } => {
+                result.push_str(&format!("<{}{}>", name, attributes));
+                if let Some(content) = content {
+                    result.push_str(&to_wikitext(content));
+                    result.push_str(&format!("</{}>", name));
+                }
+            }
+            Node::Comment(comment) => result.push_str(&format!("<!--{}-->", comment)),
+        }
+    }
+    result
+}
+
+/// Writes nodes out as text the way a reader would see it, except for templates and tags,
+/// which stay as wikitext since we can't render them here. Comments are dropped.
+pub fn to_text(nodes: &[Node]) -> String {
+    let mut result = String::new();
+    for node in nodes {
+        match node {
+            Node::Text(text) => result.push_str(text),
+            Node::Newline => result.push('\n'),
+            Node::Heading { content, .. } | Node::ListItem { content, .. } => {
+                result.push_str(&to_text(content))
+            }
+            Node::Link { target, text } => match text {
+                Some(text) => result.push_str(&to_text(text)),
+                None => result.push_str(target),
+            },
+            Node::Bold(content) | Node::Italic(content) => result.push_str(&to_text(content)),
+            Node::Comment(_) => (),
+            Node::Template { .. } | Node::Tag { .. } => {
+                result.push_str(&to_wikitext(std::slice::from_ref(node)))
+            }
+        }
+    }
+    result
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quote {
+    Bold,
+    Italic,
+}
+
+/// Where in a page templates, links and tags start that turned out not to be closed, by address.
+/// Unclosed markup inside unclosed markup would otherwise be parsed again from every place it
+/// starts, which takes twice as long for each level.
+type Unclosed = RefCell<HashSet<usize>>;
+
+/// What ends the run of inline wikitext we're in the middle of.
+#[derive(Debug, Clone, Copy, Default)]
+struct Context<'a> {
+    /// Newlines are only part of the text inside templates and tags.
+    newlines: bool,
+    /// Inside a template, `|` starts the next argument and `}}` closes it.
+    template: bool,
+    /// Inside a link's text, `]]` closes it.
+    link: bool,
+    /// Inside a tag, its closing tag ends it.
+    close_tag: Option<&'a str>,
+    bold: bool,
+    /// Whichever of bold and italic was opened last, which is the one a run of quotes closes.
+    innermost: Option<Quote>,
+    unclosed: Option<&'a Unclosed>,
+}
+
+impl<'a> Context<'a> {
+    /// Whether markup starting at the start of `input` has been found not to be closed.
+    fn is_unclosed(&self, input: &str) -> bool {
+        self.unclosed
+            .is_some_and(|unclosed| unclosed.borrow().contains(&(input.as_ptr() as usize)))
+    }
+
+    fn set_unclosed(&self, input: &str) {
+        if let Some(unclosed) = self.unclosed {
+            unclosed.borrow_mut().insert(input.as_ptr() as usize);
+        }
+    }
+
+    /// A context for the start of a line.
+    fn line(unclosed: &'a Unclosed) -> Context<'a> {
+        Context {
+            unclosed: Some(unclosed),
+            ..Context::default()
+        }
+    }
+}
+
+/// Tags whose content is taken as is, without looking for wikitext in it.
+const RAW_TAGS: &[&str] = &["nowiki", "pre", "math", "chem", "syntaxhighlight", "source"];
+/// Tags that never have a closing tag.
+const VOID_TAGS: &[&str] = &["br", "hr", "wbr", "img"];
+/// Characters that might start or end something other than text.
+const SPECIAL: &[char] = &['{', '}', '[', ']', '<', '\'', '|', '\n'];
+
+fn fail<T>(input: &str) -> IResult<&str, T> {
+    Err(nom::Err::Error(Error::new(input, ErrorKind::Verify)))
+}
+
+fn starts_with_close_tag(input: &str, name: &str) -> bool {
+    input.starts_with("</")
+        && input[2..].len() >= name.len()
+        && input.is_char_boundary(2 + name.len())
+        && input[2..2 + name.len()].eq_ignore_ascii_case(name)
+        && input[2 + name.len()..].trim_start().starts_with('>')
+}
+
+/// How many quotes at the start of `input` close the innermost bold or italic, if any.
+fn closing_quotes(input: &str, context: &Context) -> Option<usize> {
+    match context.innermost {
+        // A lone ''' inside italics opens bold, unless we're already bold.
+        Some(Quote::Italic)
+            if input.starts_with("''")
+                && !(input.starts_with("'''") && !input.starts_with("'''''") && !context.bold) =>
+        {
+            Some(2)
+        }
+        Some(Quote::Bold) if input.starts_with("'''") => Some(3),
+        _ => None,
+    }
+}
+
+fn at_end(input: &str, context: &Context) -> bool {
+    input.is_empty()
+        || (!context.newlines && input.starts_with('\n'))
+        || (context.template && (input.starts_with('|') || input.starts_with("}}")))
+        || (context.link && input.starts_with("]]"))
+        || context
+            .close_tag
+            .is_some_and(|name| starts_with_close_tag(input, name))
+        || closing_quotes(input, context).is_some()
+}
+
+/// Parses inline wikitext until whatever `context` says ends it. This never fails: anything that
+/// doesn't parse as markup is text.
+fn nodes<'a>(mut input: &'a str, context: &Context) -> IResult<&'a str, Vec<Node>> {
+    let mut result: Vec<Node> = Vec::new();
+    while !at_end(input, context) {
+        let (rest, node) = inline(input, context)?;
+        input = rest;
+        match (result.last_mut(), node) {
+            (Some(Node::Text(last)), Node::Text(text)) => last.push_str(&text),
+            (_, node) => result.push(node),
+        }
+    }
+    Ok((input, result))
+}
+
+fn inline<'a>(input: &'a str, context: &Context) -> IResult<&'a str, Node> {
+    if let Ok(result) = comment(input) {
+        return Ok(result);
+    }
+    if let Ok(result) = html_tag(input, context) {
+        return Ok(result);
+    }
+    if let Ok(result) = template(input, context) {
+        return Ok(result);
+    }
+    if let Ok(result) = link(input, context) {
+        return Ok(result);
+    }
+    if let Ok(result) = quotes(input, context) {
+        return Ok(result);
+    }
+    if let Ok((rest, text)) = take_while1::<_, _, Error<&str>>(|c| !SPECIAL.contains(&c))(input) {
+        return Ok((rest, Node::Text(text.to_owned())));
+    }
+    // Whatever special character this is, it doesn't start anything here.
+    let c = input.chars().next().unwrap();
+    Ok((&input[c.len_utf8()..], Node::Text(c.to_string())))
+}
+
+fn comment(input: &str) -> IResult<&str, Node> {
+    let (input, _) = tag("<!--")(input)?;
+    // An unclosed comment runs to the end of the page.
+    match take_until::<_, _, Error<&str>>("-->")(input) {
+        Ok((rest, comment)) => Ok((&rest[3..], Node::Comment(comment.to_owned()))),
+        Err(_) => Ok(("", Node::Comment(input.to_owned()))),
+    }
+}
+
+fn html_tag<'a>(start: &'a str, context: &Context) -> IResult<&'a str, Node> {
+    let (input, _) = tag("<")(start)?;
+    let (input, name) = take_while1(|c: char| c.is_ascii_alphanumeric())(input)?;
+    if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        return fail(input);
+    }
+    let (input, attributes) = take_while(|c| c != '>' && c != '<')(input)?;
+    let (input, _) = tag(">")(input)?;
+    let lowercase = name.to_lowercase();
+    let unclosed = Node::Tag {
+        name: name.to_owned(),
+        attributes: attributes.to_owned(),
+        content: None,
+    };
+    if attributes.ends_with('/')
+        || VOID_TAGS.contains(&lowercase.as_str())
+        || context.is_unclosed(start)
+    {
+        return Ok((input, unclosed));
+    }
+    let (rest, content) = if RAW_TAGS.contains(&lowercase.as_str()) {
+        let end = (0..input.len())
+            .filter(|&i| input.is_char_boundary(i))
+            .find(|&i| starts_with_close_tag(&input[i..], name));
+        match end {
+            Some(end) => (&input[end..], vec![Node::Text(input[..end].to_owned())]),
+            None => return Ok((input, unclosed)),
+        }
+    } else {
+        let inner = Context {
+            newlines: true,
+            close_tag: Some(name),
+            unclosed: context.unclosed,
+            ..Context::default()
+        };
+        nodes(input, &inner)?
+    };
+    if !starts_with_close_tag(rest, name) {
+        context.set_unclosed(start);
+        return Ok((input, unclosed));
+    }
+    let rest = &rest[rest.find('>').unwrap() + 1..];
+    Ok((
+        rest,
+        Node::Tag {
+            name: name.to_owned(),
+            attributes: attributes.to_owned(),
+            content: Some(content),
+        },
+    ))
+}
+
+fn template<'a>(start: &'a str, context: &Context) -> IResult<&'a str, Node> {
+    if context.is_unclosed(start) {
+        return fail(start);
+    }
+    let (mut input, _) = tag("{{")(start)?;
+    let name_end = input
+        .find(['|', '}'])
+        .filter(|&i| input[i..].starts_with('|') || input[i..].starts_with("}}"));
+    let name = match name_end {
+        Some(end) => &input[..end],
+        None => return fail(input),
+    };
+    input = &input[name.len()..];
+    let inner = Context {
+        newlines: true,
+        template: true,
+        unclosed: context.unclosed,
+        ..Context::default()
+    };
+    let mut args = Vec::new();
+    loop {
+        if let Some(rest) = input.strip_prefix("}}") {
+            return Ok((
+                rest,
+                Node::Template {
+                    name: name.to_owned(),
+                    args,
+                },
+            ));
+        }
+        input = match input.strip_prefix('|') {
+            Some(rest) => rest,
+            // We ran out of page before the template was closed.
+            None => {
+                context.set_unclosed(start);
+                return fail(input);
+            }
+        };
+        // It's a named argument if there's an = before anything fancier.
+        let arg_name = input
+            .find(|c| SPECIAL.contains(&c) || c == '=')
+            .filter(|&i| input[i..].starts_with('='))
+            .map(|i| &input[..i]);
+        if let Some(arg_name) = arg_name {
+            input = &input[arg_name.len() + 1..];
+        }
+        let (rest, value) = nodes(input, &inner)?;
+        input = rest;
+        args.push(Argument {
+            name: arg_name.map(|name| name.to_owned()),
+            value,
+        });
+    }
+}
+
+fn link<'a>(start: &'a str, context: &Context) -> IResult<&'a str, Node> {
+    if context.is_unclosed(start) {
+        return fail(start);
+    }
+    let (input, _) = tag("[[")(start)?;
+    let (input, target) = take_while1(|c| !"|[]{}<\n".contains(c))(input)?;
+    if let Some(rest) = input.strip_prefix("]]") {
+        return Ok((
+            rest,
+            Node::Link {
+                target: target.to_owned(),
+                text: None,
+            },
+        ));
+    }
+    let (input, _) = tag("|")(input)?;
+    let inner = Context {
+        link: true,
+        unclosed: context.unclosed,
+        ..Context::default()
+    };
+    let (input, text) = nodes(input, &inner)?;
+    let (input, _) = match tag::<_, _, Error<&str>>("]]")(input) {
+        Ok(result) => result,
+        Err(_) => {
+            context.set_unclosed(start);
+            return fail(input);
+        }
+    };
+    Ok((
+        input,
+        Node::Link {
+            target: target.to_owned(),
+            text: Some(text),
+        },
+    ))
+}
+
+/// Bold and italics. Like MediaWiki, they're closed at the end of the line if nothing closes
+/// them before that.
+fn quotes<'a>(input: &'a str, context: &Context) -> IResult<&'a str, Node> {
+    let (_, run) = take_while1(|c| c == '\'')(input)?;
+    let quote = match run.len() {
+        1 => return fail(input),
+        2 => Quote::Italic,
+        // ''''' is bold and italic, which we get to by opening bold first.
+        3 | 5 => Quote::Bold,
+        // Any extra quotes are just apostrophes in the text.
+        _ => return Ok((&input[1..], Node::Text("'".to_owned()))),
+    };
+    let open = if quote == Quote::Bold { 3 } else { 2 };
+    let inner = Context {
+        bold: context.bold || quote == Quote::Bold,
+        innermost: Some(quote),
+        ..*context
+    };
+    let (mut rest, content) = nodes(&input[open..], &inner)?;
+    if closing_quotes(rest, &inner).is_some() {
+        rest = &rest[open..];
+    }
+    Ok((
+        rest,
+        match quote {
+            Quote::Bold => Node::Bold(content),
+            Quote::Italic => Node::Italic(content),
+        },
+    ))
+}
+
+/// Parses a heading line, if it is one. `== Noun ==`, trailing spaces and unbalanced `=` signs
+/// are all fine; the extra `=` signs on the longer side are part of the heading text.
+fn heading<'a>(input: &'a str, unclosed: &Unclosed) -> IResult<&'a str, Node> {
+    let (input, equals) = take_while1(|c| c == '=')(input)?;
+    let (rest, mut content) = nodes(input, &Context::line(unclosed))?;
+    // Comments after the closing = signs don't count.
+    while let Some(Node::Comment(_)) = content.last() {
+        content.pop();
+    }
+    let trailing = match content.last_mut() {
+        Some(Node::Text(text)) => {
+            let trimmed = text.trim_end().len();
+            text.truncate(trimmed);
+            let trailing = text.len() - text.trim_end_matches('=').len();
+            text.truncate(text.len() - trailing);
+            trailing
+        }
+        _ => 0,
+    };
+    if trailing == 0 {
+        return fail(input);
+    }
+    let level = equals.len().min(trailing).min(6);
+    if equals.len() > level {
+        content.insert(0, Node::Text("=".repeat(equals.len() - level)));
+    }
+    if trailing > level {
+        content.push(Node::Text("=".repeat(trailing - level)));
+    }
+    content.retain(|node| node != &Node::Text(String::new()));
+    Ok((rest, Node::Heading { level, content }))
+}
+
+fn list_item<'a>(input: &'a str, unclosed: &Unclosed) -> IResult<&'a str, Node> {
+    let (input, marker) = take_while1(|c| "#*:;".contains(c))(input)?;
+    let (rest, content) = nodes(input, &Context::line(unclosed))?;
+    Ok((
+        rest,
+        Node::ListItem {
+            marker: marker.to_owned(),
+            content,
+        },
+    ))
+}
+
+/// Parses a page of wikitext. Headings and list items are lines of their own; everything else is
+/// inline, with `Node::Newline` between lines.
+pub fn parse(mut input: &str) -> Vec<Node> {
+    let mut result = Vec::new();
+    let unclosed = Unclosed::default();
+    while !input.is_empty() {
+        if let Ok((rest, node)) = heading(input, &unclosed).or_else(|_| list_item(input, &unclosed))
+        {
+            result.push(node);
+            input = rest;
+        } else {
+            let (rest, line) = nodes(input, &Context::line(&unclosed)).unwrap();
+            result.extend(line);
+            input = rest;
+        }
+        if let Some(rest) = input.strip_prefix('\n') {
+            result.push(Node::Newline);
+            input = rest;
+        }
+    }
+    result
+}
+
+/// Wiktionary's part of speech headings, one per line, with `#` comments.
+const PARTS_OF_SPEECH: &str = include_str!("parts_of_speech.txt");
+
+/// The part of speech headings we know about.
+pub fn default_parts_of_speech() -> HashSet<&'static str> {
+    PARTS_OF_SPEECH
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect()
+}
+
+/// Recognizes a part of speech heading, returning it the way it's spelled in `parts_of_speech`.
+/// Numbered headings like "Noun 2", used when a word has several etymologies, and odd casing
+/// like "Proper Noun" are accepted.
+pub fn part_of_speech<'a>(heading: &str, parts_of_speech: &HashSet<&'a str>) -> Option<&'a str> {
+    let heading = heading.trim();
+    let heading = heading
+        .trim_end_matches(|c: char| c.is_ascii_digit())
+        .trim_end();
+    parts_of_speech.get(heading).cloned().or_else(|| {
+        parts_of_speech
+            .iter()
+            .find(|pos| pos.eq_ignore_ascii_case(heading))
+            .cloned()
+    })
+}
+
+/// The number of an etymology heading: 1 for "Etymology", 2 for "Etymology 2".
+pub fn etymology_number(heading: &str) -> Option<usize> {
+    let number = heading.trim().strip_prefix("Etymology")?.trim();
+    if number.is_empty() {
+        Some(1)
+    } else {
+        number.parse().ok()
+    }
+}
+
+/// The sections besides the senses that we read something from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Section {
+    Etymology,
+    Pronunciation,
+    Translations,
+    Relations(RelationKind),
+}
+
+/// Recognizes the heading of a section we read, like "Etymology 2" or "Pronunciation".
+pub fn section(heading: &str) -> Option<Section> {
+    if etymology_number(heading).is_some() {
+        Some(Section::Etymology)
+    } else if heading.trim().starts_with("Pronunciation") {
+        Some(Section::Pronunciation)
+    } else if heading.trim() == "Translations" {
+        Some(Section::Translations)
+    } else {
+        RelationKind::ALL
+            .iter()
+            .find(|kind| kind.heading() == heading.trim())
+            .map(|kind| Section::Relations(*kind))
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub enum WikiContext {
@@ -17,48 +667,42 @@ use parse_wikitext::WikiContext::*;
 impl WikiContext {
     pub fn precedence(&self) -> u32 {
         match self {
-            &Heading1(_) => 1,
-            &Heading2(_) => 2,
-            &Heading3(_) => 3,
-            &Heading4(_) => 4,
-            &Heading5(_) => 5,
-            &Heading6(_) => 6,
+            Heading1(_) => 1,
+            Heading2(_) => 2,
+            Heading3(_) => 3,
+            Heading4(_) => 4,
+            Heading5(_) => 5,
+            Heading6(_) => 6,
         }
     }
 
     pub fn text(&self) -> &String {
         match self {
-            &Heading1(ref x) => &x,
-            &Heading2(ref x) => &x,
-            &Heading3(ref x) => &x,
-            &Heading4(ref x) => &x,
-            &Heading5(ref x) => &x,
-            &Heading6(ref x) => &x,
+            Heading1(x) | Heading2(x) | Heading3(x) | Heading4(x) | Heading5(x) | Heading6(x) => x,
         }
     }
 }
 
+#[derive(Default)]
 pub struct ContextStack {
     contexts: Vec<WikiContext>,
     pub language: Option<String>,
     pub part_of_speech: Option<String>,
     pub gender: Option<String>,
+    pub etymology: Option<usize>,
 }
 
 impl ContextStack {
     pub fn apply(
         &mut self,
         context: WikiContext,
-        languages: &HashSet<&str>,
+        languages: &LanguageFilter,
         parts_of_speech: &HashSet<&str>,
     ) {
         let new_prec = context.precedence();
         // leave only lower-precedence contexts in the stack
         let contexts = &mut self.contexts;
-        while contexts
-            .last()
-            .map_or(false, |c| c.precedence() >= new_prec)
-        {
+        while contexts.last().is_some_and(|c| c.precedence() >= new_prec) {
             // TODO: Check if this check is even necessary
             match contexts.pop() {
                 None => (),
@@ -66,224 +710,1585 @@ impl ContextStack {
                     if self.language.as_ref().unwrap_or(&String::from("")) == context.text() {
                         self.language = None;
                     }
-                    if self.part_of_speech.as_ref().unwrap_or(&String::from("")) == context.text() {
+                    let popped = part_of_speech(context.text(), parts_of_speech);
+                    if popped.is_some() && self.part_of_speech.as_deref() == popped {
                         self.part_of_speech = None;
                     }
                 }
             }
         }
-        if languages.contains(context.text().as_str()) {
-            self.language = Some(context.text().clone());
+        // Etymologies are numbered within a language.
+        if new_prec <= 2 {
+            self.etymology = None;
         }
-        if parts_of_speech.contains(context.text().as_str()) {
-            self.part_of_speech = Some(context.text().clone());
+        if let Some(number) = etymology_number(context.text()) {
+            self.etymology = Some(number);
         }
-        if context.text().starts_with("{{") && context.text().ends_with("}}") {
-            let (lang, pos, gender) = parse_template(
-                &context
-                    .text()
-                    .get(2..context.text().len() - 2)
-                    .unwrap_or(""),
-            );
-            match lang {
-                Some(s) => self.language = Some(s),
-                None => (),
-            };
-            match pos {
-                Some(s) => self.part_of_speech = Some(s),
-                None => (),
-            };
-            match gender {
-                Some(s) => self.gender = Some(s),
-                None => (),
-            };
+        if let Heading2(name) = &context {
+            if languages.accepts(name) {
+                self.language = Some(name.clone());
+            }
+        }
+        if let Some(pos) = part_of_speech(context.text(), parts_of_speech) {
+            self.part_of_speech = Some(pos.to_owned());
+            // The gender comes from the headword line under this heading, if there is one.
+            self.gender = None;
         }
         contexts.push(context);
     }
 
+    /// Takes what we can from a headword template. The heading above it usually says the same,
+    /// but the template is what has the gender.
+    pub fn apply_headword(
+        &mut self,
+        headword: &Headword,
+        language_codes: &HashMap<String, String>,
+        languages: &LanguageFilter,
+    ) {
+        if let Some(name) = language_codes.get(&headword.code) {
+            if languages.accepts(name) {
+                self.language = Some(name.clone());
+            }
+        }
+        if headword.part_of_speech.is_some() {
+            self.part_of_speech = headword.part_of_speech.clone();
+        }
+        self.gender = if headword.genders.is_empty() {
+            None
+        } else {
+            Some(headword.genders.join(", "))
+        };
+    }
+
     pub fn new() -> ContextStack {
         ContextStack {
             contexts: Vec::new(),
             language: None,
             part_of_speech: None,
             gender: None,
+            etymology: None,
+        }
+    }
+}
+
+/// Short part of speech names used in headword template names, like `en-adj`.
+const POS_ABBREVIATIONS: &[(&str, &str)] = &[
+    ("adj", "Adjective"),
+    ("adv", "Adverb"),
+    ("con", "Conjunction"),
+    ("conj", "Conjunction"),
+    ("det", "Determiner"),
+    ("interj", "Interjection"),
+    ("num", "Numeral"),
+    ("part", "Particle"),
+    ("postp", "Postposition"),
+    ("prep", "Preposition"),
+    ("pron", "Pronoun"),
+    ("proper", "Proper noun"),
+];
+
+/// What a headword template, like `{{head|de|noun|g=m}}` or `{{fr-noun|f}}`, says about an entry.
+#[derive(Debug, Default, PartialEq)]
+pub struct Headword {
+    /// The template's name, like "head" or "fr-noun".
+    pub template: String,
+    /// The language code, like "de".
+    pub code: String,
+    pub part_of_speech: Option<String>,
+    pub genders: Vec<String>,
+    /// Every argument, with positional ones numbered from 1 like MediaWiki does.
+    pub args: HashMap<String, String>,
+}
+
+impl Headword {
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args
+            .get(name)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// The values of a parameter that can be repeated with a number, like `g`, `g2`, `g3`.
+    pub fn numbered(&self, name: &str) -> Vec<&str> {
+        let first = self.arg(name).or_else(|| self.arg(&format!("{}1", name)));
+        first
+            .into_iter()
+            .chain((2..).map_while(|i| self.arg(&format!("{}{}", name, i))))
+            .collect()
+    }
+
+    /// Plurals given with `pl=`, `pl2=`..., or as `plural` inflections of `{{head}}`.
+    pub fn plurals(&self) -> Vec<&str> {
+        let mut plurals = self.numbered("pl");
+        let mut i = 3;
+        while let Some(label) = self.arg(&i.to_string()) {
+            if label == "plural" {
+                plurals.extend(self.arg(&(i + 1).to_string()));
+            }
+            i += 2;
+        }
+        plurals
+    }
+
+    /// The inflected forms the headword gives, with their tags, like ("Hunde", "plural").
+    /// Only forms that are spelled out are read: working out the rest, like "stopped" from
+    /// `{{en-verb|++}}`, takes the rules in the template's module.
+    pub fn forms(&self) -> Vec<(String, String)> {
+        let mut forms: Vec<(String, String)> = Vec::new();
+        let positional: Vec<&str> = (1..).map_while(|i| self.arg(&i.to_string())).collect();
+        // Old-style English templates give a stem and the endings to put on it, like
+        // `{{en-verb|stopp|ed}}`, and none of their arguments are forms.
+        let old_style = positional.iter().any(|arg| OLD_STYLE_ENDINGS.contains(arg));
+        let mut push = |form: Option<&str>, tags: &str| {
+            if let Some(form) = form.filter(|form| is_spelled_out(form)) {
+                forms.push((form.to_owned(), tags.to_owned()));
+            }
+        };
+        match self.template.as_str() {
+            // Pairs of a label and a form, after the code and the part of speech.
+            "head" => {
+                let mut i = 3;
+                while let Some(label) = self.arg(&i.to_string()) {
+                    push(self.arg(&(i + 1).to_string()), label);
+                    i += 2;
+                }
+            }
+            "en-noun" if !old_style => {
+                for plural in &positional {
+                    push(Some(plural), "plural");
+                }
+            }
+            "en-verb" if !old_style => {
+                let tags = [
+                    "third-person singular simple present",
+                    "present participle",
+                    "simple past",
+                    "past participle",
+                ];
+                for (form, tags) in positional.iter().zip(tags.iter()) {
+                    push(Some(form), tags);
+                }
+            }
+            "en-adj" | "en-adv" if !old_style => {
+                for (form, tags) in positional.iter().zip(["comparative", "superlative"].iter()) {
+                    push(Some(form), tags);
+                }
+            }
+            _ => (),
+        }
+        for (name, tags) in NAMED_FORMS {
+            for form in self.numbered(name) {
+                push(Some(form), tags);
+            }
+        }
+        forms
+    }
+}
+
+/// Parameters that many headword templates give inflected forms in, and what those forms are.
+const NAMED_FORMS: &[(&str, &str)] = &[
+    ("pl", "plural"),
+    ("f", "feminine"),
+    ("fpl", "feminine plural"),
+    ("m", "masculine"),
+    ("mpl", "masculine plural"),
+    ("gen", "genitive"),
+    ("comp", "comparative"),
+    ("sup", "superlative"),
+    ("past", "past"),
+    ("pp", "past participle"),
+    ("dim", "diminutive"),
+];
+
+/// The endings old-style English headword templates put after a stem.
+const OLD_STYLE_ENDINGS: &[&str] = &[
+    "s", "es", "ies", "d", "ed", "ied", "ing", "y", "i", "er", "est",
+];
+
+/// Whether an argument of a headword template is a form rather than a marker standing for one,
+/// like "+" for the regular form, "-" for none, or "more" for "more big".
+fn is_spelled_out(form: &str) -> bool {
+    !(form.starts_with('+')
+        || form.contains('<')
+        || matches!(form, "*" | "-" | "~" | "!" | "?" | "more" | "most"))
+}
+
+/// A template's arguments by name, with positional ones numbered from 1 like MediaWiki does.
+fn named_args(args: &[Argument]) -> HashMap<String, String> {
+    let mut result = HashMap::new();
+    let mut position = 0;
+    for arg in args {
+        let name = match arg.name() {
+            Some(name) => name.to_owned(),
+            None => {
+                position += 1;
+                position.to_string()
+            }
+        };
+        result.insert(name, to_text(&arg.value));
+    }
+    result
+}
+
+/// Whether a positional argument looks like a gender, like "m", "f-p" or "mf".
+fn is_gender(arg: &str) -> bool {
+    let (gender, number) = arg.split_once('-').unwrap_or((arg, ""));
+    matches!(gender, "m" | "f" | "n" | "c" | "mf" | "mfbysense" | "?")
+        && matches!(number, "" | "s" | "p" | "d")
+}
+
+/// Whether `code` could be a language code. If we have the language table, it has to be in it.
+fn is_language_code(code: &str, language_codes: &HashMap<String, String>) -> bool {
+    if !language_codes.is_empty() {
+        return language_codes.contains_key(code);
+    }
+    !code.is_empty()
+        && code.split('-').all(|part| {
+            (2..=3).contains(&part.len()) && part.chars().all(|c| c.is_ascii_lowercase())
+        })
+}
+
+/// Recognizes a part of speech as it's written in headword templates: "noun", "nouns", "adj".
+fn template_part_of_speech<'a>(pos: &str, parts_of_speech: &HashSet<&'a str>) -> Option<&'a str> {
+    let pos = pos.trim();
+    let short = POS_ABBREVIATIONS
+        .iter()
+        .find(|(short, _)| *short == pos)
+        .map(|(_, pos)| *pos);
+    short
+        .and_then(|pos| part_of_speech(pos, parts_of_speech))
+        .or_else(|| part_of_speech(pos, parts_of_speech))
+        .or_else(|| part_of_speech(pos.strip_suffix('s')?, parts_of_speech))
+}
+
+/// Reads a headword template: either the generic `{{head|<code>|<pos>|...}}` or a
+/// language-specific `{{<code>-<pos>|...}}`. Returns `None` for any other template.
+pub fn parse_headword(
+    template: &Node,
+    language_codes: &HashMap<String, String>,
+    parts_of_speech: &HashSet<&str>,
+) -> Option<Headword> {
+    let (name, args) = match template {
+        Node::Template { name, args } => (name.trim(), args),
+        _ => return None,
+    };
+    let mut headword = Headword {
+        template: name.to_owned(),
+        args: named_args(args),
+        ..Headword::default()
+    };
+
+    if name == "head" {
+        headword.code = headword.arg("1")?.to_owned();
+        let pos = headword.arg("2").unwrap_or("");
+        headword.part_of_speech = template_part_of_speech(pos, parts_of_speech).map(String::from);
+    } else {
+        // Codes can have hyphens too, like gem-pro-noun, so try every split.
+        let (code, pos) = name
+            .match_indices('-')
+            .map(|(i, _)| (&name[..i], &name[i + 1..]))
+            .find(|(code, pos)| {
+                is_language_code(code, language_codes)
+                    && template_part_of_speech(pos, parts_of_speech).is_some()
+            })?;
+        headword.code = code.to_owned();
+        headword.part_of_speech = template_part_of_speech(pos, parts_of_speech).map(String::from);
+    }
+
+    headword.genders = headword
+        .numbered("g")
+        .into_iter()
+        .map(String::from)
+        .collect();
+    // Romance nouns, like {{fr-noun|m}}, give the gender as the first argument.
+    let noun = matches!(
+        headword.part_of_speech.as_deref(),
+        Some("Noun") | Some("Proper noun")
+    );
+    if headword.genders.is_empty() && noun && name != "head" {
+        if let Some(gender) = headword.arg("1").filter(|arg| is_gender(arg)) {
+            headword.genders.push(gender.to_owned());
+        }
+    }
+    Some(headword)
+}
+
+/// How form-of templates abbreviate grammatical tags, like "3" and "pres" in
+/// `{{inflection of|en|go||3|s|pres}}`.
+const INFLECTION_TAGS: &[(&str, &str)] = &[
+    ("1", "first-person"),
+    ("2", "second-person"),
+    ("3", "third-person"),
+    ("s", "singular"),
+    ("sg", "singular"),
+    ("p", "plural"),
+    ("pl", "plural"),
+    ("d", "dual"),
+    ("m", "masculine"),
+    ("f", "feminine"),
+    ("n", "neuter"),
+    ("c", "common"),
+    ("an", "animate"),
+    ("inan", "inanimate"),
+    ("nom", "nominative"),
+    ("acc", "accusative"),
+    ("gen", "genitive"),
+    ("dat", "dative"),
+    ("ins", "instrumental"),
+    ("loc", "locative"),
+    ("voc", "vocative"),
+    ("abl", "ablative"),
+    ("par", "partitive"),
+    ("pres", "present"),
+    ("pret", "preterite"),
+    ("impf", "imperfect"),
+    ("perf", "perfect"),
+    ("plup", "pluperfect"),
+    ("fut", "future"),
+    ("aor", "aorist"),
+    ("ind", "indicative"),
+    ("sub", "subjunctive"),
+    ("subj", "subjunctive"),
+    ("imp", "imperative"),
+    ("cond", "conditional"),
+    ("inf", "infinitive"),
+    ("part", "participle"),
+    ("ptcp", "participle"),
+    ("ger", "gerund"),
+    ("act", "active"),
+    ("pass", "passive"),
+    ("mid", "middle"),
+    ("pos", "positive"),
+    ("comd", "comparative"),
+    ("comp", "comparative"),
+    ("supd", "superlative"),
+    ("sup", "superlative"),
+    ("def", "definite"),
+    ("indef", "indefinite"),
+    ("str", "strong"),
+    ("wk", "weak"),
+    ("mix", "mixed"),
+];
+
+/// Spells out grammatical tags, like ["3", "s", "pres"] as "third-person singular present".
+/// Tags we don't know are kept as they are.
+pub fn inflection_tags(tags: &[&str]) -> String {
+    let spell = |tag: &str| {
+        INFLECTION_TAGS
+            .iter()
+            .find(|(short, _)| *short == tag)
+            .map_or(tag.to_owned(), |(_, long)| long.to_string())
+    };
+    tags.iter()
+        .map(|tag| tag.trim())
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| match tag.as_bytes() {
+            // Person and number can go together, like "3s".
+            [b'1'..=b'3', b's' | b'p' | b'd'] => {
+                format!("{} {}", spell(&tag[..1]), spell(&tag[1..]))
+            }
+            // Either of two, like "m//f".
+            _ => tag.split("//").map(spell).collect::<Vec<_>>().join("/"),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Form-of templates that always name the same inflection, like `{{plural of|en|cat}}`.
+/// Language-specific ones, like `{{en-past of|go}}`, go by the name after the code.
+const FORM_OF_TEMPLATES: &[(&str, &str)] = &[
+    ("plural of", "plural"),
+    ("singular of", "singular"),
+    ("past tense of", "past tense"),
+    ("past of", "past tense"),
+    ("simple past of", "simple past"),
+    ("past participle of", "past participle"),
+    ("present participle of", "present participle"),
+    ("ing form of", "present participle"),
+    ("third-person singular of", "third-person singular"),
+    ("gerund of", "gerund"),
+    ("participle of", "participle"),
+    ("infinitive of", "infinitive"),
+    ("masculine of", "masculine"),
+    ("masculine plural of", "masculine plural"),
+    ("feminine of", "feminine"),
+    ("feminine singular of", "feminine singular"),
+    ("feminine plural of", "feminine plural"),
+    ("neuter of", "neuter"),
+    ("comparative of", "comparative"),
+    ("superlative of", "superlative"),
+    ("inflected form of", ""),
+    ("verb form of", ""),
+    ("noun form of", ""),
+    ("adj form of", ""),
+];
+
+/// Reads a template saying that a definition is an inflected form of another word, like
+/// `{{inflection of|de|gehen||1|p|pret|ind}}`, `{{plural of|en|cat}}` or
+/// `{{form of|en|archaic third-person singular|go}}`. Returns the word and the tags of each
+/// inflection, as `{{inflection of}}` can name several, separated by `;`.
+pub fn parse_form_of(template: &Node) -> Option<(String, Vec<String>)> {
+    let (name, args) = match template {
+        Node::Template { name, args } => (name.trim(), args),
+        _ => return None,
+    };
+    let known = |name: &str| {
+        matches!(name, "inflection of" | "infl of" | "form of")
+            || FORM_OF_TEMPLATES.iter().any(|(other, _)| *other == name)
+    };
+    // Language-specific templates leave out the code, and so do old ones that give it as lang=.
+    let (code_given, name) = match name.split_once('-') {
+        Some((code, rest)) if is_language_code(code, &HashMap::new()) && known(rest) => {
+            (true, rest)
+        }
+        _ if known(name) => (args.iter().any(|arg| arg.name() == Some("lang")), name),
+        _ => return None,
+    };
+    let positional: Vec<String> = args
+        .iter()
+        .filter(|arg| arg.name().is_none())
+        .skip(if code_given { 0 } else { 1 })
+        .map(|arg| to_text(&arg.value).trim().to_owned())
+        .collect();
+    // `{{form of}}` says what the form is before the word.
+    let (lemma, rest) = match name {
+        "form of" => (positional.get(1)?, positional.get(..1).unwrap_or(&[])),
+        _ => (positional.first()?, positional.get(1..).unwrap_or(&[])),
+    };
+    let lemma = lemma.split('#').next().unwrap().trim().to_owned();
+    if lemma.is_empty() {
+        return None;
+    }
+    let inflections = match name {
+        // After the word comes the text to show for it, which we don't need.
+        "inflection of" | "infl of" => {
+            let tags: Vec<&str> = rest.iter().skip(1).map(String::as_str).collect();
+            tags.split(|tag| *tag == ";").map(inflection_tags).collect()
+        }
+        "form of" => rest.to_vec(),
+        _ => FORM_OF_TEMPLATES
+            .iter()
+            .filter(|(other, _)| *other == name)
+            .map(|(_, tags)| tags.to_string())
+            .collect(),
+    };
+    let inflections = inflections
+        .into_iter()
+        .filter(|tags: &String| !tags.is_empty())
+        .collect();
+    Some((lemma, inflections))
+}
+
+/// The template a line consists of, give or take whitespace and comments.
+fn lone_template(line: &[Node]) -> Option<&Node> {
+    let mut nodes = line.iter().filter(|node| match node {
+        Node::Text(text) => !text.trim().is_empty(),
+        Node::Comment(_) => false,
+        _ => true,
+    });
+    match (nodes.next(), nodes.next()) {
+        (Some(template @ Node::Template { .. }), None) => Some(template),
+        _ => None,
+    }
+}
+
+/// The first year in a date or a citation, like "1851" in "'''1851''', Herman Melville".
+pub fn find_year(text: &str) -> Option<String> {
+    text.split(|c: char| !c.is_ascii_digit())
+        .find(|digits| (3..=4).contains(&digits.len()))
+        .map(String::from)
+}
+
+/// Reads a usage example or quotation template, like `{{ux|en|text|translation}}`,
+/// `{{quote-book|en|year=1851|author=...|passage=...}}` or `{{RQ:Shakespeare Hamlet|...}}`.
+/// Returns `None` for any other template.
+pub fn parse_example(template: &Node) -> Option<Example> {
+    let (name, args) = match template {
+        Node::Template { name, args } => (name.trim(), named_args(args)),
+        _ => return None,
+    };
+    let arg = |name: &str| {
+        args.get(name)
+            .map(|value| value.trim())
+            .filter(|value| !value.is_empty())
+            .map(String::from)
+    };
+    let first = |names: &[&str]| names.iter().find_map(|name| arg(name));
+
+    let mut example = Example::default();
+    if matches!(name, "ux" | "uxi" | "usex" | "eg") {
+        example.text = arg("2").unwrap_or_default();
+        example.translation = first(&["t", "translation", "3"]);
+    } else if name.ends_with("-usex") || name.ends_with("-x") {
+        // Language-specific ones, like {{ja-usex}}, leave out the code, and some have a reading
+        // before the translation.
+        example.text = arg("1").unwrap_or_default();
+        let last = (2..).map_while(|i| arg(&i.to_string())).last();
+        example.translation = first(&["t", "translation"]).or(last);
+    } else if let Some(source) = name.strip_prefix("RQ:") {
+        example.text = first(&["passage", "text"]).unwrap_or_default();
+        example.translation = first(&["t", "translation"]);
+        example.source = Some(source.trim().to_owned());
+        example.year = first(&["year", "date"]).and_then(|date| find_year(&date));
+    } else if name.starts_with("quote-") {
+        example.text = first(&["passage", "text", "7"]).unwrap_or_default();
+        example.translation = first(&["t", "translation", "8"]);
+        let source: Vec<String> = vec![
+            first(&["author", "3"]),
+            first(&["title", "work", "journal", "4"]),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        if !source.is_empty() {
+            example.source = Some(source.join(", "));
+        }
+        example.year = first(&["year", "date", "2"]).and_then(|date| find_year(&date));
+    } else {
+        return None;
+    }
+    Some(example)
+}
+
+/// Adds what a `#:` or `#*` line under a sense says to the sense's examples. `kind` is what
+/// comes after the `#`s: `:` for examples, `*` for quotations, and one more `:` for the
+/// quotation's passage or the example's translation.
+fn add_example(examples: &mut Vec<Example>, kind: &str, content: &[Node]) {
+    let template = lone_template(content);
+    let text = to_text(content).trim().to_owned();
+    match kind {
+        ":" | "*" => match template {
+            Some(template) => examples.extend(parse_example(template)),
+            None if kind == ":" => examples.push(Example {
+                text,
+                ..Example::default()
+            }),
+            // An old-style citation, with the passage on the `#*:` line after it.
+            None => examples.push(Example {
+                year: find_year(&text),
+                source: Some(text.trim_end_matches(':').trim_end().to_owned()),
+                ..Example::default()
+            }),
+        },
+        "*:" => match examples.last_mut() {
+            Some(example) if example.text.is_empty() => example.text = text,
+            _ => examples.push(Example {
+                text,
+                ..Example::default()
+            }),
+        },
+        "::" | "*::" => {
+            if let Some(example) = examples.last_mut() {
+                if example.translation.is_none() {
+                    example.translation = Some(text);
+                }
+            }
+        }
+        _ => (),
+    }
+}
+
+/// Reads the pronunciations on a line of a Pronunciation section, like
+/// `* {{a|en|UK}} {{IPA|en|/bæŋk/}}`. Accents at the start of the line go for everything on it.
+pub fn parse_pronunciations(
+    line: &[Node],
+    language: &str,
+    etymology: Option<usize>,
+) -> Vec<Pronunciation> {
+    let mut result = Vec::new();
+    let mut line_accents: Vec<String> = Vec::new();
+    walk(line, &mut |node| {
+        let (name, args) = match node {
+            Node::Template { name, args } => (name.trim(), args),
+            _ => return,
+        };
+        let named = named_args(args);
+        let arg = |name: &str| {
+            named
+                .get(name)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+        };
+        // Older templates give the language as lang= rather than first.
+        let values: Vec<String> = args
+            .iter()
+            .filter(|arg| arg.name().is_none())
+            .skip(if arg("lang").is_some() { 0 } else { 1 })
+            .map(|arg| to_text(&arg.value).trim().to_owned())
+            .collect();
+        let mut accents = line_accents.clone();
+        accents.extend(arg("a").map(String::from));
+        let accent = if accents.is_empty() {
+            None
+        } else {
+            Some(accents.join(", "))
+        };
+        let mut push = |kind: PronunciationKind, value: String| {
+            result.push(Pronunciation {
+                language: language.to_owned(),
+                etymology,
+                kind,
+                value,
+                accent: accent.clone(),
+            })
+        };
+        match name {
+            "a" | "accent" => {
+                let accents = args.iter().filter(|arg| arg.name().is_none());
+                let accents = accents.map(|arg| to_text(&arg.value).trim().to_owned());
+                // The language code came first only in newer versions.
+                line_accents.extend(
+                    accents
+                        .enumerate()
+                        .filter(|(i, accent)| *i > 0 || !is_language_code(accent, &HashMap::new()))
+                        .map(|(_, accent)| accent),
+                );
+            }
+            "IPA" => {
+                for ipa in values.into_iter().filter(|ipa| !ipa.is_empty()) {
+                    push(PronunciationKind::Ipa, ipa);
+                }
+            }
+            "audio" => {
+                if let Some(file) = values.into_iter().next().filter(|file| !file.is_empty()) {
+                    push(PronunciationKind::Audio, file);
+                }
+            }
+            "rhymes" | "rhyme" => {
+                for rhyme in values.into_iter().filter(|rhyme| !rhyme.is_empty()) {
+                    push(PronunciationKind::Rhymes, rhyme);
+                }
+            }
+            // Several hyphenations are separated by an empty argument.
+            "hyph" | "hyphenation" => {
+                for syllables in values.split(|syllable| syllable.is_empty()) {
+                    if !syllables.is_empty() {
+                        push(PronunciationKind::Hyphenation, syllables.join("‧"));
+                    }
+                }
+            }
+            _ => (),
+        }
+    });
+    result
+}
+
+/// Reads the translations on a line of a translation table, like
+/// `* French: {{t+|fr|banque|f}}`. `gloss` is kept up to date with the `{{trans-top}}` lines
+/// that start each table.
+pub fn parse_translations(
+    line: &[Node],
+    gloss: &mut Option<String>,
+    language: &str,
+    part_of_speech: Option<&String>,
+) -> Vec<Translation> {
+    let mut result = Vec::new();
+    walk(line, &mut |node| {
+        let (name, args) = match node {
+            Node::Template { name, args } => (name.trim(), named_args(args)),
+            _ => return,
+        };
+        let arg = |name: &str| {
+            args.get(name)
+                .map(|value| value.trim())
+                .filter(|value| !value.is_empty())
+                .map(String::from)
+        };
+        match name {
+            "trans-top" | "trans-top-also" => *gloss = arg("1"),
+            "checktrans-top" | "trans-bottom" => *gloss = None,
+            "t" | "t+" | "tt" | "tt+" | "t-check" | "t+check" | "t-simple" => {
+                let (code, word) = match (arg("1"), arg("2")) {
+                    (Some(code), Some(word)) => (code, word),
+                    _ => return,
+                };
+                let mut genders: Vec<String> = (3..).map_while(|i| arg(&i.to_string())).collect();
+                genders.extend(arg("g"));
+                result.push(Translation {
+                    language: language.to_owned(),
+                    part_of_speech: part_of_speech.cloned(),
+                    gloss: gloss.clone(),
+                    code,
+                    word,
+                    gender: if genders.is_empty() {
+                        None
+                    } else {
+                        Some(genders.join(", "))
+                    },
+                    transliteration: arg("tr"),
+                });
+            }
+            _ => (),
+        }
+    });
+    result
+}
+
+/// Cleans up a related word as it's given in a template: inline modifiers like `<q:formal>` are
+/// dropped, and so is the namespace of a thesaurus entry.
+fn relation_target(term: &str) -> Option<String> {
+    let term = term.split('<').next().unwrap().trim();
+    let term = term.strip_prefix("Thesaurus:").unwrap_or(term).trim();
+    if term.is_empty() || term == ";" {
+        None
+    } else {
+        Some(term.to_owned())
+    }
+}
+
+/// The positional arguments of a template that lists words, without the language code.
+fn listed_terms(args: &[Argument]) -> Vec<String> {
+    let lang = args.iter().any(|arg| arg.name() == Some("lang"));
+    args.iter()
+        .filter(|arg| arg.name().is_none())
+        .skip(if lang { 0 } else { 1 })
+        .filter_map(|arg| relation_target(&to_text(&arg.value)))
+        .collect()
+}
+
+/// Reads a template listing related words under a sense, like `{{syn|en|talk|chatter}}`.
+pub fn parse_sense_relations(template: &Node) -> Option<(RelationKind, Vec<String>)> {
+    let (name, args) = match template {
+        Node::Template { name, args } => (name.trim(), args),
+        _ => return None,
+    };
+    let kind = match name {
+        "syn" | "synonyms" => RelationKind::Synonym,
+        "ant" | "antonyms" => RelationKind::Antonym,
+        "hyper" | "hypernyms" => RelationKind::Hypernym,
+        "hypo" | "hyponyms" => RelationKind::Hyponym,
+        "mero" | "meronyms" => RelationKind::Meronym,
+        "holo" | "holonyms" => RelationKind::Holonym,
+        "tropo" | "troponyms" => RelationKind::Troponym,
+        "cot" | "coord" | "coordinate terms" => RelationKind::CoordinateTerm,
+        _ => return None,
+    };
+    Some((kind, listed_terms(args)))
+}
+
+/// Whether a template lists words in columns, like `{{col3|en|...}}` or `{{der3|en|...}}`.
+fn is_column_list(name: &str) -> bool {
+    let name = name.strip_suffix("-u").unwrap_or(name);
+    let base = name.trim_end_matches(|c: char| c.is_ascii_digit());
+    matches!(base, "col" | "der" | "rel" | "hyp" | "col-auto") && name.len() - base.len() <= 1
+}
+
+/// Reads the related words on a line of a section like "Synonyms", such as
+/// `* {{sense|financial institution}} {{l|en|depository}}, [[treasury]]`.
+pub fn parse_relations(
+    line: &[Node],
+    kind: RelationKind,
+    context_stack: &ContextStack,
+) -> Vec<Relation> {
+    let line = match line {
+        [Node::ListItem { content, .. }] => content,
+        line => line,
+    };
+    let mut gloss = None;
+    let mut targets = Vec::new();
+    for node in line {
+        match node {
+            Node::Template { name, args } => {
+                let name = name.trim();
+                let arg = |i: usize| {
+                    args.iter()
+                        .filter(|arg| arg.name().is_none())
+                        .nth(i)
+                        .map(|arg| to_text(&arg.value).trim().to_owned())
+                        .filter(|value| !value.is_empty())
+                };
+                match name {
+                    "sense" | "s" => gloss = arg(0),
+                    "l" | "l-self" | "ll" | "link" => targets.extend(arg(1)),
+                    _ if is_column_list(name) => targets.extend(listed_terms(args)),
+                    _ => (),
+                }
+            }
+            Node::Link { target, .. } if !target.contains(':') => {
+                targets.push(target.split('#').next().unwrap().to_owned());
+            }
+            _ => (),
+        }
+    }
+    targets
+        .into_iter()
+        .filter_map(|target| relation_target(&target))
+        .map(|target| Relation {
+            language: context_stack.language.clone().unwrap(),
+            etymology: context_stack.etymology,
+            part_of_speech: context_stack.part_of_speech.clone(),
+            kind,
+            target,
+            sense: None,
+            gloss: gloss.clone(),
+        })
+        .collect()
+}
+
+/// Ties relations that say which sense they're for, with a gloss, to the one sense whose
+/// definition mentions that gloss, if there's just one.
+pub fn link_relations(relations: &mut [Relation], meanings: &[Meaning]) {
+    for relation in relations
+        .iter_mut()
+        .filter(|relation| relation.sense.is_none())
+    {
+        let gloss = match &relation.gloss {
+            Some(gloss) => gloss.to_lowercase(),
+            None => continue,
+        };
+        let mut senses = meanings.iter().enumerate().filter(|(_, meaning)| {
+            meaning.language == relation.language
+                && meaning.etymology == relation.etymology
+                && relation
+                    .part_of_speech
+                    .as_ref()
+                    .is_none_or(|pos| *pos == meaning.part_of_speech)
+                && meaning.definition.to_lowercase().contains(&gloss)
+        });
+        if let (Some((index, _)), None) = (senses.next(), senses.next()) {
+            relation.sense = Some(index);
+        }
+    }
+}
+
+/// The label of the `n`th sense at `depth`, counting from 1: numbers at the top, then letters,
+/// then roman numerals, like Wiktionary's nested lists.
+pub fn sense_label(depth: usize, n: usize) -> String {
+    match depth {
+        2 if n <= 26 => ((b'a' + n as u8 - 1) as char).to_string(),
+        3 if n < 40 => {
+            let (tens, ones) = (n / 10, n % 10);
+            let ones = ["", "i", "ii", "iii", "iv", "v", "vi", "vii", "viii", "ix"][ones];
+            format!("{}{}", "x".repeat(tens), ones)
+        }
+        _ => n.to_string(),
+    }
+}
+
+/// Keeps track of where we are in a section's nested list of senses.
+#[derive(Default)]
+pub struct SenseCounter {
+    /// For each depth, how many senses we've seen and the index of the last one, if we kept it.
+    levels: Vec<(usize, Option<usize>)>,
+}
+
+impl SenseCounter {
+    /// Starts numbering from 1 again, for a new section.
+    pub fn reset(&mut self) {
+        self.levels.clear();
+    }
+
+    /// The index of the last sense at `depth` in this section, if we kept it.
+    pub fn current(&self, depth: usize) -> Option<usize> {
+        self.levels
+            .get(depth.checked_sub(1)?)
+            .and_then(|level| level.1)
+    }
+
+    /// Counts a sense at `depth`, returning its ordinal and its parent. `index` is where the
+    /// sense is being kept, if it is.
+    pub fn next(&mut self, depth: usize, index: Option<usize>) -> (String, Option<usize>) {
+        self.levels.truncate(depth);
+        while self.levels.len() < depth {
+            // A sub-sense without a sense above it is numbered as if there were one.
+            let missing = self.levels.len() + 1 < depth;
+            self.levels.push((missing as usize, None));
         }
+        self.levels[depth - 1].0 += 1;
+        self.levels[depth - 1].1 = index;
+        let ordinal = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(i, (n, _))| sense_label(i + 1, *n))
+            .collect::<Vec<_>>()
+            .join(".");
+        let parent = depth
+            .checked_sub(2)
+            .and_then(|parent| self.levels[parent].1);
+        (ordinal, parent)
     }
 }
 
+/// Pulls the definitions out of a page of wikitext, along with the language and part of speech
+/// of the section each one is in, and the etymologies they come under. `language_codes` maps
+/// codes to names, for headword templates. `title` is the page's, which is the word's name.
 pub fn parse_wikitext(
+    title: &str,
     text: String,
-    languages: &HashSet<&str>,
+    languages: &LanguageFilter,
+    language_codes: &HashMap<String, String>,
     parts_of_speech: &HashSet<&str>,
-) -> Vec<Meaning> {
+) -> Word {
     let mut result: Vec<Meaning> = Vec::new();
+    let mut etymologies: Vec<Etymology> = Vec::new();
+    let mut pronunciations: Vec<Pronunciation> = Vec::new();
+    let mut translations: Vec<Translation> = Vec::new();
+    let mut relations: Vec<Relation> = Vec::new();
+    let mut forms: Vec<WordForm> = Vec::new();
+    // The sense the translation table we're in is for.
+    let mut gloss: Option<String> = None;
+    // The section we're in, if it's one we read and it's in a language we're after.
+    let mut current_section: Option<Section> = None;
     let mut context_stack: ContextStack = ContextStack::new();
+    let mut senses = SenseCounter::default();
 
-    let stack_apply = |context_stack: &mut ContextStack,
-                       wiki_context: &dyn Fn(String) -> WikiContext,
-                       line: &str,
-                       slice: &Option<&str>| {
-        slice.map_or_else(
-            || {
-                println!("Could not parse line: {}", line);
-            },
-            |slice| {
-                context_stack.apply(wiki_context(slice.to_owned()), languages, parts_of_speech);
-            },
+    let nodes = parse(&text);
+    for line in nodes.split(|node| node == &Node::Newline) {
+        match line {
+            [Node::Heading { level, content }] => {
+                let text = to_text(content).trim().to_owned();
+                let context = match level {
+                    1 => Heading1(text),
+                    2 => Heading2(text),
+                    3 => Heading3(text),
+                    4 => Heading4(text),
+                    5 => Heading5(text),
+                    _ => Heading6(text),
+                };
+                let heading = context.text().clone();
+                context_stack.apply(context, languages, parts_of_speech);
+                senses.reset();
+                gloss = None;
+                current_section = context_stack
+                    .language
+                    .as_ref()
+                    .and_then(|_| section(&heading));
+                if let Some(Section::Etymology) = current_section {
+                    etymologies.push(Etymology {
+                        language: context_stack.language.clone().unwrap(),
+                        number: context_stack.etymology.unwrap(),
+                        text: String::new(),
+                    });
+                }
+            }
+            line if current_section == Some(Section::Etymology) => {
+                let text = &mut etymologies.last_mut().unwrap().text;
+                text.push_str(&to_text(line));
+                text.push('\n');
+            }
+            line if current_section == Some(Section::Pronunciation) => {
+                pronunciations.extend(parse_pronunciations(
+                    line,
+                    context_stack.language.as_ref().unwrap(),
+                    context_stack.etymology,
+                ));
+            }
+            line if matches!(current_section, Some(Section::Relations(_))) => {
+                if let Some(Section::Relations(kind)) = current_section {
+                    relations.extend(parse_relations(line, kind, &context_stack));
+                }
+            }
+            line if current_section == Some(Section::Translations) => {
+                translations.extend(parse_translations(
+                    line,
+                    &mut gloss,
+                    context_stack.language.as_ref().unwrap(),
+                    context_stack.part_of_speech.as_ref(),
+                ));
+            }
+            // Senses are #, sub-senses ##, and so on. Examples and quotations go under them.
+            [Node::ListItem { marker, content }] if marker.starts_with('#') => {
+                let depth = marker.chars().take_while(|&c| c == '#').count();
+                let kind = &marker[depth..];
+                if !kind.is_empty() {
+                    if let Some(index) = senses.current(depth) {
+                        let sense = &result[index];
+                        match lone_template(content).and_then(parse_sense_relations) {
+                            Some((kind, targets)) => {
+                                relations.extend(targets.into_iter().map(|target| Relation {
+                                    language: sense.language.clone(),
+                                    etymology: sense.etymology,
+                                    part_of_speech: Some(sense.part_of_speech.clone()),
+                                    kind,
+                                    target,
+                                    sense: Some(index),
+                                    gloss: None,
+                                }))
+                            }
+                            None => add_example(&mut result[index].examples, kind, content),
+                        }
+                    }
+                    continue;
+                }
+                match (&context_stack.language, &context_stack.part_of_speech) {
+                    (Some(language), Some(part_of_speech)) => {
+                        // Senses like "past tense of go" make this page a form of that word.
+                        let mut form_of = None;
+                        walk(content, &mut |node| {
+                            if let Some((lemma, inflections)) = parse_form_of(node) {
+                                if form_of.is_none() {
+                                    form_of = Some(lemma.clone());
+                                }
+                                let inflections = if inflections.is_empty() {
+                                    vec![String::new()]
+                                } else {
+                                    inflections
+                                };
+                                forms.extend(inflections.into_iter().map(|tags| WordForm {
+                                    language: language.clone(),
+                                    part_of_speech: part_of_speech.clone(),
+                                    lemma: lemma.clone(),
+                                    form: title.to_owned(),
+                                    tags,
+                                }));
+                            }
+                        });
+                        let (ordinal, parent) = senses.next(depth, Some(result.len()));
+                        result.push(Meaning {
+                            language: language.clone(),
+                            part_of_speech: part_of_speech.clone(),
+                            gender: context_stack.gender.clone(),
+                            definition: to_text(content).trim().to_owned(),
+                            depth,
+                            ordinal,
+                            parent,
+                            examples: Vec::new(),
+                            etymology: context_stack.etymology,
+                            form_of,
+                        });
+                    }
+                    _ => {
+                        senses.next(depth, None);
+                    }
+                }
+            }
+            line => {
+                let headword = lone_template(line)
+                    .and_then(|template| parse_headword(template, language_codes, parts_of_speech));
+                if let Some(headword) = headword {
+                    context_stack.apply_headword(&headword, language_codes, languages);
+                    if let (Some(language), Some(part_of_speech)) =
+                        (&context_stack.language, &context_stack.part_of_speech)
+                    {
+                        forms.extend(headword.forms().into_iter().map(|(form, tags)| WordForm {
+                            language: language.clone(),
+                            part_of_speech: part_of_speech.clone(),
+                            lemma: title.to_owned(),
+                            form,
+                            tags,
+                        }));
+                    }
+                }
+            }
+        }
+    }
+    // Citations whose passage never turned up.
+    for meaning in &mut result {
+        meaning.examples.retain(|example| !example.text.is_empty());
+    }
+    for etymology in &mut etymologies {
+        etymology.text = etymology.text.trim().to_owned();
+    }
+    etymologies.retain(|etymology| !etymology.text.is_empty());
+    link_relations(&mut relations, &result);
+    Word {
+        name: title.to_owned(),
+        meanings: result,
+        etymologies,
+        pronunciations,
+        translations,
+        relations,
+        forms,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Node {
+        Node::Text(s.to_owned())
+    }
+
+    /// Parses the page `title` in every language, with no language codes.
+    fn parse_entry(title: &str, wikitext: &str) -> Word {
+        parse_wikitext(
+            title,
+            wikitext.to_owned(),
+            &LanguageFilter::Any,
+            &HashMap::new(),
+            &default_parts_of_speech(),
+        )
+    }
+
+    fn headings(wikitext: &str) -> Vec<(usize, String)> {
+        parse(wikitext)
+            .iter()
+            .filter_map(|node| match node {
+                Node::Heading { level, content } => Some((*level, to_text(content))),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn headings_allow_spacing_and_trailing_whitespace() {
+        assert_eq!(
+            headings("==English==  \n=== Noun ===\n====Verb==== <!-- x -->\n"),
+            vec![
+                (2, "English".to_owned()),
+                (3, " Noun ".to_owned()),
+                (4, "Verb".to_owned())
+            ]
         );
-    };
+    }
+
+    #[test]
+    fn unbalanced_headings_keep_the_extra_equals_signs() {
+        assert_eq!(
+            headings("===Noun==\n==Verb===\n"),
+            vec![(2, "=Noun".to_owned()), (2, "Verb=".to_owned())]
+        );
+        assert_eq!(headings("==Noun\n"), vec![]);
+    }
+
+    #[test]
+    fn templates_have_nested_and_named_arguments() {
+        let nodes = parse("{{lb|en|{{m|fr|chat}}|g=m|[[a|b]]}}");
+        assert_eq!(
+            nodes,
+            vec![Node::Template {
+                name: "lb".to_owned(),
+                args: vec![
+                    Argument {
+                        name: None,
+                        value: vec![text("en")],
+                    },
+                    Argument {
+                        name: None,
+                        value: vec![Node::Template {
+                            name: "m".to_owned(),
+                            args: vec![
+                                Argument {
+                                    name: None,
+                                    value: vec![text("fr")],
+                                },
+                                Argument {
+                                    name: None,
+                                    value: vec![text("chat")],
+                                },
+                            ],
+                        }],
+                    },
+                    Argument {
+                        name: Some("g".to_owned()),
+                        value: vec![text("m")],
+                    },
+                    Argument {
+                        name: None,
+                        value: vec![Node::Link {
+                            target: "a".to_owned(),
+                            text: Some(vec![text("b")]),
+                        }],
+                    },
+                ],
+            }]
+        );
+    }
 
-    for line in text.lines() {
-        if line.starts_with("======") && line.len() > 12 {
-            stack_apply(
-                &mut context_stack,
-                &|x| Heading6(x),
-                line,
-                &line.get(6..line.len() - 6),
-            );
-        } else if line.starts_with("=====") && line.len() > 10 {
-            stack_apply(
-                &mut context_stack,
-                &|x| Heading5(x),
-                line,
-                &line.get(5..line.len() - 5),
-            );
-        } else if line.starts_with("====") && line.len() > 8 {
-            stack_apply(
-                &mut context_stack,
-                &|x| Heading4(x),
-                line,
-                &line.get(4..line.len() - 4),
-            );
-        } else if line.starts_with("===") && line.len() > 6 {
-            stack_apply(
-                &mut context_stack,
-                &|x| Heading3(x),
-                line,
-                &line.get(3..line.len() - 3),
-            );
-        } else if line.starts_with("==") && line.len() > 4 {
-            stack_apply(
-                &mut context_stack,
-                &|x| Heading2(x),
-                line,
-                &line.get(2..line.len() - 2),
-            );
-        } else if line.starts_with("=") && line.len() > 2 {
-            stack_apply(
-                &mut context_stack,
-                &|x| Heading1(x),
-                line,
-                &line.get(1..line.len() - 1),
-            );
-        } else if line.starts_with("{{") && line.ends_with("}}") {
-            stack_apply(
-                &mut context_stack,
-                &|x| Heading6(x),
-                line,
-                &line.get(0..line.len()),
-            );
-        } else if line.starts_with("# ") {
-            context_stack.language.as_ref().and_then(|language| {
-                context_stack.part_of_speech.as_ref().map(|part_of_speech| {
-                    result.push(Meaning {
-                        language: language.clone(),
-                        part_of_speech: part_of_speech.clone(),
-                        gender: context_stack.gender.clone(),
-                        definition: String::from(&line[2..]),
-                    })
-                })
-            });
+    #[test]
+    fn text_drops_links_formatting_and_comments() {
+        let nodes = parse("# A [[small]] '''[[feline|cat]]''' <!-- hi -->{{lb|en|x}} ''really''");
+        match &nodes[..] {
+            [Node::ListItem { marker, content }] => {
+                assert_eq!(marker, "#");
+                assert_eq!(to_text(content), " A small cat {{lb|en|x}} really");
+            }
+            _ => panic!("not a list item: {:?}", nodes),
         }
     }
-    result
-}
 
-fn parse_template(line: &str) -> (Option<String>, Option<String>, Option<String>) {
-    let mut tokens = line.rsplit('|').collect::<Vec<&str>>();
-    let mut gender: Option<String> = None;
-    let (lang, pos) = match tokens.pop() {
-        None => (None, None),
-        Some(s) => match s {
-            // English
-            "en-adj" => (Some("English".to_string()), Some("Adjective".to_string())),
-            "en-adv" => (Some("English".to_string()), Some("Adverb".to_string())),
-            "en-con" => (Some("English".to_string()), Some("Conjuction".to_string())),
-            "en-det" => (Some("English".to_string()), Some("Determiner".to_string())),
-            "en-interj" => (
-                Some("English".to_string()),
-                Some("Interjection".to_string()),
-            ),
-            "en-noun" => (Some("English".to_string()), Some("Noun".to_string())),
-            "en-part" => (Some("English".to_string()), Some("Particle".to_string())),
-            "en-prefix" => (Some("English".to_string()), Some("Prefix".to_string())),
-            "en-prep" => (Some("English".to_string()), Some("Preposition".to_string())),
-            "en-prep phrase" => (
-                Some("English".to_string()),
-                Some("Prepositional Phrase".to_string()),
-            ),
-            "en-pron" => (Some("English".to_string()), Some("Pronoun".to_string())),
-            "en-proper noun" => (Some("English".to_string()), Some("Proper Noun".to_string())),
-            "en-proverb" => (Some("English".to_string()), Some("Proverb".to_string())),
-            "en-suffix" => (Some("English".to_string()), Some("Suffix".to_string())),
-            "en-symbol" => (Some("English".to_string()), Some("Symbol".to_string())),
-            "en-verb" => (Some("English".to_string()), Some("Verb".to_string())),
-
-            // French
-            "fr-adjective" => (Some("French".to_string()), Some("Adjective".to_string())),
-            "fr-adverb" => (Some("French".to_string()), Some("Adverb".to_string())),
-            "fr-card-adj" => (
-                Some("French".to_string()),
-                Some("Cardinal Adjective".to_string()),
-            ),
-            "fr-card-inv" => (Some("French".to_string()), Some("card-inv".to_string())),
-            "fr-card-noun" => {
-                gender = Some("".to_string());
+    #[test]
+    fn bold_and_italic_nest() {
+        assert_eq!(
+            parse("'''''both''''' ''it '''bold''' it'' '''open"),
+            vec![
+                Node::Bold(vec![Node::Italic(vec![text("both")])]),
+                text(" "),
+                Node::Italic(vec![
+                    text("it "),
+                    Node::Bold(vec![text("bold")]),
+                    text(" it"),
+                ]),
+                text(" "),
+                Node::Bold(vec![text("open")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn tags_and_unclosed_markup() {
+        let nodes = parse("a<br>b<ref name=\"x\">{{c}}</ref><nowiki>[[d]]</nowiki> {{e [[f");
+        assert_eq!(
+            nodes,
+            vec![
+                text("a"),
+                Node::Tag {
+                    name: "br".to_owned(),
+                    attributes: String::new(),
+                    content: None,
+                },
+                text("b"),
+                Node::Tag {
+                    name: "ref".to_owned(),
+                    attributes: " name=\"x\"".to_owned(),
+                    content: Some(vec![Node::Template {
+                        name: "c".to_owned(),
+                        args: vec![],
+                    }]),
+                },
+                Node::Tag {
+                    name: "nowiki".to_owned(),
+                    attributes: String::new(),
+                    content: Some(vec![text("[[d]]")]),
+                },
+                text(" {{e [[f"),
+            ]
+        );
+    }
+
+    #[test]
+    fn numbered_and_oddly_cased_parts_of_speech() {
+        let parts_of_speech = default_parts_of_speech();
+        assert_eq!(part_of_speech("Noun 2", &parts_of_speech), Some("Noun"));
+        assert_eq!(
+            part_of_speech(" Numeral ", &parts_of_speech),
+            Some("Numeral")
+        );
+        assert_eq!(
+            part_of_speech("Proper Noun", &parts_of_speech),
+            Some("Proper noun")
+        );
+        assert_eq!(part_of_speech("Etymology 1", &parts_of_speech), None);
+    }
+
+    fn headword(wikitext: &str, codes: &[(&str, &str)]) -> Option<Headword> {
+        let codes = codes
+            .iter()
+            .map(|(code, name)| (code.to_string(), name.to_string()))
+            .collect();
+        parse_headword(&parse(wikitext)[0], &codes, &default_parts_of_speech())
+    }
+
+    #[test]
+    fn generic_headword_templates() {
+        let head = headword("{{head|de|nouns|g=m|g2=n|plural|Hunde}}", &[]).unwrap();
+        assert_eq!(head.code, "de");
+        assert_eq!(head.part_of_speech.as_deref(), Some("Noun"));
+        assert_eq!(head.genders, vec!["m", "n"]);
+        assert_eq!(head.plurals(), vec!["Hunde"]);
+        assert_eq!(
+            headword("{{head|tpi|conj}}", &[])
+                .unwrap()
+                .part_of_speech
+                .as_deref(),
+            Some("Conjunction")
+        );
+    }
+
+    #[test]
+    fn language_specific_headword_templates() {
+        let head = headword("{{fr-noun|f|pl=chattes}}", &[]).unwrap();
+        assert_eq!(head.code, "fr");
+        assert_eq!(head.genders, vec!["f"]);
+        assert_eq!(head.plurals(), vec!["chattes"]);
+        let head = headword("{{gem-pro-proper noun|g=m}}", &[]).unwrap();
+        assert_eq!(head.code, "gem-pro");
+        assert_eq!(head.part_of_speech.as_deref(), Some("Proper noun"));
+        assert_eq!(
+            headword("{{en-verb|chats}}", &[]).unwrap().genders,
+            Vec::<String>::new()
+        );
+        assert_eq!(headword("{{der-top}}", &[]), None);
+        assert_eq!(headword("{{xx-noun}}", &[("en", "English")]), None);
+    }
+
+    #[test]
+    fn sub_senses_are_numbered_and_linked() {
+        let wikitext = "==English==\n===Noun===\n# one\n## one a\n#: example\n## one b\n### deep\n# two\n\n===Verb===\n## orphan\n";
+        let meanings = parse_entry("big", wikitext).meanings;
+        let senses: Vec<(&str, &str, usize, Option<usize>)> = meanings
+            .iter()
+            .map(|m| (m.definition.as_str(), m.ordinal.as_str(), m.depth, m.parent))
+            .collect();
+        assert_eq!(
+            senses,
+            vec![
+                ("one", "1", 1, None),
+                ("one a", "1.a", 2, Some(0)),
+                ("one b", "1.b", 2, Some(0)),
+                ("deep", "1.b.i", 3, Some(2)),
+                ("two", "2", 1, None),
+                ("orphan", "1.a", 2, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn examples_and_quotations_go_under_their_sense() {
+        let wikitext = "==English==\n===Noun===\n# one\n#: ''plain''\n#:: translated\n\
+                        ## one a\n##: {{ux|en|usage|gloss}}\n#: {{syn|en|x}}\n\
+                        #* {{quote-book|en|year=1851|author=A|title=B|passage=quoted}}\n\
+                        #* '''1920''', C, ''D'':\n#*: cited\n#* {{RQ:E F|passage=rq}}\n";
+        let meanings = parse_entry("big", wikitext).meanings;
+        fn example(
+            text: &str,
+            translation: Option<&str>,
+            source: Option<&str>,
+            year: Option<&str>,
+        ) -> Example {
+            Example {
+                text: text.to_owned(),
+                translation: translation.map(String::from),
+                source: source.map(String::from),
+                year: year.map(String::from),
+            }
+        }
+        assert_eq!(
+            meanings[0].examples,
+            vec![
+                example("plain", Some("translated"), None, None),
+                example("quoted", None, Some("A, B"), Some("1851")),
+                example("cited", None, Some("1920, C, D"), Some("1920")),
+                example("rq", None, Some("E F"), None),
+            ]
+        );
+        assert_eq!(
+            meanings[1].examples,
+            vec![example("usage", Some("gloss"), None, None)]
+        );
+    }
+
+    #[test]
+    fn senses_belong_to_their_etymology() {
+        let wikitext = "==English==\n===Etymology 1===\nFrom {{der|en|it|banca}}.\n\
+                        ====Noun====\n# place for money\n===Etymology 2===\nOld Norse.\n\
+                        ====Noun====\n# edge of a river\n==Danish==\n===Etymology===\nx\n\
+                        ===Noun===\n# bench\n";
+        let word = parse_entry("big", wikitext);
+        let etymologies: Vec<(&str, usize, &str)> = word
+            .etymologies
+            .iter()
+            .map(|e| (e.language.as_str(), e.number, e.text.as_str()))
+            .collect();
+        assert_eq!(
+            etymologies,
+            vec![
+                ("English", 1, "From {{der|en|it|banca}}."),
+                ("English", 2, "Old Norse."),
+                ("Danish", 1, "x"),
+            ]
+        );
+        let senses: Vec<Option<usize>> = word.meanings.iter().map(|m| m.etymology).collect();
+        assert_eq!(senses, vec![Some(1), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn pronunciations_with_accents() {
+        let line = parse("* {{a|en|UK}} {{IPA|en|/bɑːθ/|[bɑːθ]}} {{audio|en|En-uk-bath.ogg}}");
+        let line = parse_pronunciations(&line, "English", Some(2))
+            .into_iter()
+            .map(|p| (p.kind, p.value, p.accent, p.etymology))
+            .collect::<Vec<_>>();
+        let uk = Some("UK".to_owned());
+        assert_eq!(
+            line,
+            vec![
+                (
+                    PronunciationKind::Ipa,
+                    "/bɑːθ/".to_owned(),
+                    uk.clone(),
+                    Some(2)
+                ),
+                (
+                    PronunciationKind::Ipa,
+                    "[bɑːθ]".to_owned(),
+                    uk.clone(),
+                    Some(2)
+                ),
+                (
+                    PronunciationKind::Audio,
+                    "En-uk-bath.ogg".to_owned(),
+                    uk,
+                    Some(2)
+                ),
+            ]
+        );
+        let line = parse("* {{hyph|nl|ba|den||bad|en}} {{rhymes|nl|ɑt}}");
+        let values: Vec<String> = parse_pronunciations(&line, "Dutch", None)
+            .into_iter()
+            .map(|p| p.value)
+            .collect();
+        assert_eq!(values, vec!["ba‧den", "bad‧en", "ɑt"]);
+    }
+
+    #[test]
+    fn translations_are_grouped_by_gloss() {
+        let mut gloss = None;
+        let noun = "Noun".to_owned();
+        let mut translations = Vec::new();
+        for line in "{{trans-top|money}}\n* French: {{t+|fr|banque|f}}\n\
+                     * Japanese: {{t|ja|銀行|tr=ginkō}}\n{{trans-bottom}}\n\
+                     * German: {{t|de|Bank|f|g=n}}"
+            .lines()
+        {
+            translations.extend(parse_translations(
+                &parse(line),
+                &mut gloss,
+                "English",
+                Some(&noun),
+            ));
+        }
+        let translations: Vec<_> = translations
+            .iter()
+            .map(|t| {
                 (
-                    Some("French".to_string()),
-                    Some("Cardinal Noun".to_string()),
+                    t.gloss.as_deref(),
+                    t.code.as_str(),
+                    t.word.as_str(),
+                    t.gender.as_deref(),
+                    t.transliteration.as_deref(),
                 )
-            }
-            "fr-conjunction" => (Some("French".to_string()), Some("Conjuction".to_string())),
-            "fr-det" => (Some("French".to_string()), Some("Determiner".to_string())),
-            "fr-diacretical mark" => (
-                Some("French".to_string()),
-                Some("Diacretical Mark".to_string()),
-            ),
-            "fr-interj" => (Some("French".to_string()), Some("Interjection".to_string())),
-            "fr-letter" => (Some("French".to_string()), Some("Letter".to_string())),
-            "fr-noun" => {
-                gender = Some("".to_string());
-                (Some("French".to_string()), Some("Noun".to_string()))
-            }
-            "fr-past participle" => (
-                Some("French".to_string()),
-                Some("Past Participle".to_string()),
-            ),
-            "fr-phrase" => (Some("French".to_string()), Some("Phrase".to_string())),
-            "fr-prefix" => (Some("French".to_string()), Some("Prefix".to_string())),
-            "fr-postposition" => (Some("French".to_string()), Some("Postposition".to_string())),
-            "fr-preposition" => (Some("French".to_string()), Some("Preposition".to_string())),
-            "fr-pronoun" => (Some("French".to_string()), Some("Pronoun".to_string())),
-            "fr-proper noun" => {
-                gender = Some("".to_string());
-                (Some("French".to_string()), Some("Proper Noun".to_string()))
-            }
-            "fr-punctuation mark" => (
-                Some("French".to_string()),
-                Some("Punctuation Mark".to_string()),
-            ),
-            "fr-proverb" => (Some("French".to_string()), Some("Proverb".to_string())),
-            "fr-suffix" => (Some("French".to_string()), Some("Suffix".to_string())),
-            "fr-verb" => (Some("French".to_string()), Some("Verb".to_string())),
-
-            _ => (None, None),
-        },
-    };
+            })
+            .collect();
+        assert_eq!(
+            translations,
+            vec![
+                (Some("money"), "fr", "banque", Some("f"), None),
+                (Some("money"), "ja", "銀行", None, Some("ginkō")),
+                (None, "de", "Bank", Some("f, n"), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn relations_from_sections_and_senses() {
+        let wikitext = "==English==\n===Adjective===\n# Of great size.\n\
+                        #: {{syn|en|large|huge<q:informal>}}\n# Important.\n\
+                        ====Synonyms====\n* {{sense|important}} {{l|en|significant}}, [[major]]\n\
+                        ====Derived terms====\n{{col3|en|bigness|big deal}}\n";
+        let word = parse_entry("big", wikitext);
+        let relations: Vec<_> = word
+            .relations
+            .iter()
+            .map(|r| (r.kind, r.target.as_str(), r.sense))
+            .collect();
+        assert_eq!(
+            relations,
+            vec![
+                (RelationKind::Synonym, "large", Some(0)),
+                (RelationKind::Synonym, "huge", Some(0)),
+                (RelationKind::Synonym, "significant", Some(1)),
+                (RelationKind::Synonym, "major", Some(1)),
+                (RelationKind::DerivedTerm, "bigness", None),
+                (RelationKind::DerivedTerm, "big deal", None),
+            ]
+        );
+        assert_eq!(word.meanings[0].examples, vec![]);
+    }
+
+    #[test]
+    fn forms_from_headwords_and_form_of_senses() {
+        let forms = |wikitext: &str| {
+            headword(wikitext, &[])
+                .unwrap()
+                .forms()
+                .into_iter()
+                .map(|(form, tags)| format!("{}: {}", tags, form))
+                .collect::<Vec<_>>()
+        };
+        let none = Vec::<String>::new();
+        assert_eq!(
+            forms("{{en-verb|stops|stopping|stopped|stopped}}"),
+            vec![
+                "third-person singular simple present: stops",
+                "present participle: stopping",
+                "simple past: stopped",
+                "past participle: stopped",
+            ]
+        );
+        // Forms the template works out for itself aren't guessed at.
+        assert_eq!(forms("{{en-verb}}"), none);
+        assert_eq!(forms("{{en-verb|++}}"), none);
+        assert_eq!(forms("{{en-verb|*}}"), none);
+        assert_eq!(forms("{{en-verb|stopp|ed}}"), none);
+        assert_eq!(forms("{{en-verb|carr|i|ed}}"), none);
+        assert_eq!(forms("{{en-adj|er}}"), none);
+        assert_eq!(forms("{{en-adj|more}}"), none);
+        assert_eq!(
+            forms("{{en-adj|bigger|biggest}}"),
+            vec!["comparative: bigger", "superlative: biggest"]
+        );
+        assert_eq!(forms("{{en-noun|~|carrys}}"), vec!["plural: carrys"]);
+        assert_eq!(forms("{{en-noun|-}}"), none);
+        assert_eq!(forms("{{en-noun|s<q:rare>}}"), none);
+        assert_eq!(
+            forms("{{head|de|noun|genitive|Hundes|plural|Hunde}}"),
+            vec!["genitive: Hundes", "plural: Hunde"]
+        );
 
-    if gender.is_some() {
-        gender = tokens.pop().map(|s| s.to_string());
+        let wikitext = "==German==\n===Verb===\n{{head|de|verb form}}\n\
+                        # {{inflection of|de|gehen||1|p|pret|ind|;|3p|pret|sub}}\n";
+        let word = parse_entry("gingen", wikitext);
+        assert_eq!(word.meanings[0].form_of.as_deref(), Some("gehen"));
+        let forms: Vec<_> = word
+            .forms
+            .iter()
+            .map(|f| (f.lemma.as_str(), f.form.as_str(), f.tags.as_str()))
+            .collect();
+        assert_eq!(
+            forms,
+            vec![
+                (
+                    "gehen",
+                    "gingen",
+                    "first-person plural preterite indicative"
+                ),
+                (
+                    "gehen",
+                    "gingen",
+                    "third-person plural preterite subjunctive"
+                ),
+            ]
+        );
     }
 
-    (lang, pos, gender)
+    #[test]
+    fn form_of_templates_name_their_lemma() {
+        let form_of = |wikitext: &str| parse_form_of(&parse(wikitext)[0]);
+        let owned = |lemma: &str, tags: &[&str]| {
+            Some((
+                lemma.to_owned(),
+                tags.iter().map(|tags| tags.to_string()).collect::<Vec<_>>(),
+            ))
+        };
+        assert_eq!(form_of("{{plural of|en|cat}}"), owned("cat", &["plural"]));
+        assert_eq!(form_of("{{en-past of|go}}"), owned("go", &["past tense"]));
+        assert_eq!(
+            form_of("{{form of|en|archaic third-person singular|go}}"),
+            owned("go", &["archaic third-person singular"])
+        );
+        assert_eq!(
+            form_of("{{inflection of|gehen#German||2s|pres|lang=de}}"),
+            owned("gehen", &["second-person singular present"])
+        );
+        assert_eq!(form_of("{{de-inflected form of|Hund}}"), owned("Hund", &[]));
+        assert_eq!(form_of("{{alternative form of|en|color}}"), None);
+    }
+
+    #[test]
+    fn deeply_unclosed_markup_is_parsed() {
+        for open in &["{{x|", "[[a|", "<span>", "{{x|[[a|<span>"] {
+            let source = format!("{}end", open.repeat(30));
+            assert_eq!(to_wikitext(&parse(&source)), source);
+        }
+    }
+
+    #[test]
+    fn wikitext_round_trips() {
+        let source =
+            "==English==\n{{en-noun|es}}\n\n# {{lb|en|x}} [[a|b]] <sup>2</sup>\n#: ex\n{{{1|}}}";
+        assert_eq!(to_wikitext(&parse(source)), source);
+    }
 }
@@ -1,79 +1,968 @@
+extern crate bzip2;
+extern crate flate2;
 extern crate quick_xml;
-use parse_xml::quick_xml::{events::Event, Reader};
+extern crate zstd;
+use parse_xml::bzip2::read::{BzDecoder, MultiBzDecoder};
+use parse_xml::flate2::read::MultiGzDecoder;
+use parse_xml::quick_xml::events::{BytesStart, Event};
+use parse_xml::quick_xml::Reader;
 
-use std::io::BufRead;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
 
-use Page;
+use {Namespace, Page, Revision};
 
-fn parse_revision<B: BufRead>(reader: &mut Reader<B>) -> Option<String> {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    None,
+    Bzip2,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Guesses the compression format from the first few bytes of a file, falling back to the
+    /// file extension, if there is one, when the magic bytes aren't recognized.
+    pub fn detect(magic: &[u8], path: Option<&Path>) -> Compression {
+        if magic.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if magic.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if magic.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Compression::Zstd
+        } else {
+            match path
+                .and_then(|path| path.extension())
+                .and_then(|ext| ext.to_str())
+            {
+                Some("bz2") => Compression::Bzip2,
+                Some("gz") => Compression::Gzip,
+                Some("zst") => Compression::Zstd,
+                _ => Compression::None,
+            }
+        }
+    }
+}
+
+/// Wraps `input` in a decoder if it is bzip2, gzip or zstd compressed. `path` is only used to
+/// guess the format from its extension if the magic bytes don't give it away.
+pub fn decompress<'a, R: BufRead + 'a>(
+    mut input: R,
+    path: Option<&Path>,
+) -> io::Result<Box<dyn BufRead + 'a>> {
+    let compression = Compression::detect(input.fill_buf()?, path);
+    Ok(match compression {
+        Compression::None => Box::new(input),
+        // Wikimedia's multistream dumps are many bz2 streams glued together, so we need the
+        // multi-stream decoder to read past the first one.
+        Compression::Bzip2 => Box::new(BufReader::new(MultiBzDecoder::new(input))),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(input))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::with_buffer(
+            input,
+        )?)),
+    })
+}
+
+/// Opens a dump for streaming, decompressing it on the fly if it is a .bz2, .gz or .zst file.
+pub fn open_dump(path: &Path) -> io::Result<Box<dyn BufRead>> {
+    decompress(BufReader::new(File::open(path)?), Some(path))
+}
+
+/// Counts the bytes read through it, so we can tell how far into a compressed dump we are.
+pub struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R, count: Arc<AtomicU64>) -> CountingReader<R> {
+        CountingReader { inner, count }
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+impl<R: BufRead> BufRead for CountingReader<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.count.fetch_add(amt as u64, Ordering::Relaxed);
+        self.inner.consume(amt)
+    }
+}
+
+#[derive(Debug)]
+pub enum ParseErrorKind {
+    Io(io::Error),
+    Xml(quick_xml::Error),
+    /// The dump ended in the middle of a page.
+    UnexpectedEof,
+}
+
+/// An error reading a dump, with enough context to find the offending page.
+#[derive(Debug)]
+pub struct ParseError {
+    pub kind: ParseErrorKind,
+    /// Byte offset into the decompressed XML, relative to the start of `block` if there is one.
+    pub position: u64,
+    /// For multistream dumps, the byte offset of the bz2 block the error is in.
+    pub block: Option<u64>,
+    /// The title of the page the error is in, if we got far enough to know it.
+    pub title: Option<String>,
+}
+
+impl ParseError {
+    fn new<B>(kind: ParseErrorKind, reader: &Reader<B>, title: Option<String>) -> ParseError {
+        ParseError {
+            kind,
+            position: reader.buffer_position() as u64,
+            block: None,
+            title,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError {
+            kind: ParseErrorKind::Io(e),
+            position: 0,
+            block: None,
+            title: None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::Io(ref e) => write!(f, "{}", e)?,
+            ParseErrorKind::Xml(ref e) => write!(f, "{}", e)?,
+            ParseErrorKind::UnexpectedEof => write!(f, "unexpected end of file")?,
+        }
+        write!(f, " at position {}", self.position)?;
+        if let Some(block) = self.block {
+            write!(f, " of the block at {}", block)?;
+        }
+        if let Some(ref title) = self.title {
+            write!(f, " in page {:?}", title)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ParseError {}
+
+impl From<quick_xml::Error> for ParseErrorKind {
+    fn from(e: quick_xml::Error) -> ParseErrorKind {
+        ParseErrorKind::Xml(e)
+    }
+}
+
+/// Reads the next event, treating the end of the file as an error since callers are always in
+/// the middle of an element.
+fn read_event<'b, B: BufRead>(
+    reader: &mut Reader<B>,
+    buf: &'b mut Vec<u8>,
+) -> Result<Event<'b>, ParseErrorKind> {
+    match reader.read_event_into(buf)? {
+        Event::Eof => Err(ParseErrorKind::UnexpectedEof),
+        event => Ok(event),
+    }
+}
+
+/// Reads the text inside the element that was just opened, up to its closing tag. quick-xml can
+/// hand us the text in several pieces, e.g. around CDATA sections or comments, so we have to
+/// stitch them back together.
+fn read_text<B: BufRead>(reader: &mut Reader<B>) -> Result<Option<String>, ParseErrorKind> {
+    let mut buf = Vec::new();
+    let mut result: Option<String> = None;
+    loop {
+        match read_event(reader, &mut buf)? {
+            Event::Text(e) => result
+                .get_or_insert_with(String::new)
+                .push_str(&e.unescape()?),
+            Event::CData(e) => result
+                .get_or_insert_with(String::new)
+                .push_str(&String::from_utf8_lossy(&e.into_inner())),
+            Event::End(_) => break,
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok(result)
+}
+
+fn parse_revision<B: BufRead>(
+    reader: &mut Reader<B>,
+) -> Result<(Revision, Option<String>), ParseErrorKind> {
     let mut buf = Vec::new();
+    let mut revision = Revision::default();
     let mut result = None;
+    let mut in_contributor = false;
     loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) if e.name().as_ref() == b"text" => {
-                let mut buf = Vec::new();
-                match reader.read_event_into(&mut buf) {
-                    Ok(Event::Text(e)) => {
-                        let text = e.unescape().unwrap().to_string();
-                        result = Some(text);
+        match read_event(reader, &mut buf)? {
+            Event::Start(ref e) => match e.name().as_ref() {
+                b"text" => result = read_text(reader)?,
+                b"contributor" => in_contributor = true,
+                // The contributor has an <id> too, but we only care about their name.
+                b"id" if !in_contributor => {
+                    revision.id = read_text(reader)?
+                        .and_then(|id| id.parse().ok())
+                        .unwrap_or(0)
+                }
+                b"username" | b"ip" if in_contributor => revision.contributor = read_text(reader)?,
+                b"timestamp" => revision.timestamp = read_text(reader)?.unwrap_or_default(),
+                b"sha1" => revision.sha1 = read_text(reader)?.unwrap_or_default(),
+                _ => (),
+            },
+            Event::End(ref e) => match e.name().as_ref() {
+                b"contributor" => in_contributor = false,
+                b"revision" => break,
+                _ => (),
+            },
+            _ => (),
+        }
+        buf.clear();
+    }
+    Ok((revision, result))
+}
+
+/// Site-wide information from the `<siteinfo>` header of a dump.
+#[derive(Debug, Clone, Default)]
+pub struct SiteInfo {
+    /// The URL of the wiki's main page, like "https://en.wiktionary.org/wiki/Wiktionary:Main_Page".
+    pub base: Option<String>,
+    /// Localized namespace names by id.
+    pub namespaces: HashMap<i32, String>,
+}
+
+impl SiteInfo {
+    pub fn namespace(&self, id: i32) -> Namespace {
+        Namespace {
+            id,
+            name: self.namespaces.get(&id).cloned().unwrap_or_default(),
+        }
+    }
+
+    /// Works out the namespace of a title from its prefix, for pages without an `<ns>` element.
+    pub fn namespace_of_title(&self, title: &str) -> Namespace {
+        title
+            .split_once(':')
+            .and_then(|(prefix, _)| {
+                self.namespaces
+                    .iter()
+                    .find(|&(_, name)| !name.is_empty() && name == prefix)
+            })
+            .map_or_else(|| self.namespace(::NS_MAIN), |(&id, _)| self.namespace(id))
+    }
+}
+
+fn namespace_key(e: &BytesStart) -> Option<i32> {
+    e.try_get_attribute("key")
+        .ok()
+        .and_then(|key| key)
+        .and_then(|key| String::from_utf8_lossy(&key.value).parse::<i32>().ok())
+}
+
+pub fn parse_siteinfo<B: BufRead>(reader: &mut Reader<B>) -> Result<SiteInfo, ParseError> {
+    let mut buf = Vec::new();
+    let mut siteinfo = SiteInfo::default();
+    let mut parse = || -> Result<(), ParseErrorKind> {
+        loop {
+            match read_event(reader, &mut buf)? {
+                // The main namespace has no name, so it's written as <namespace key="0" />.
+                Event::Empty(ref e) if e.name().as_ref() == b"namespace" => {
+                    if let Some(key) = namespace_key(e) {
+                        siteinfo.namespaces.insert(key, String::new());
                     }
-                    _ => (),
                 }
+                Event::Start(ref e) if e.name().as_ref() == b"base" => {
+                    siteinfo.base = read_text(reader)?;
+                }
+                Event::Start(ref e) if e.name().as_ref() == b"namespace" => {
+                    let key = namespace_key(e);
+                    if let (Some(key), Some(name)) = (key, read_text(reader)?) {
+                        siteinfo.namespaces.insert(key, name);
+                    }
+                }
+                Event::End(ref e) if e.name().as_ref() == b"siteinfo" => return Ok(()),
+                _ => (),
             }
-            Ok(Event::End(ref e)) if e.name().as_ref() == b"revision" => break,
-            _ => (),
+            buf.clear();
         }
+    };
+    match parse() {
+        Ok(()) => Ok(siteinfo),
+        Err(kind) => Err(ParseError::new(kind, reader, None)),
     }
-    result
 }
 
-pub fn parse_page<B: BufRead>(mut reader: &mut Reader<B>) -> Option<Page> {
+/// Parses a `<page>` element whose start tag was just read. Pages without any text come back
+/// as `None`.
+pub fn parse_page<B: BufRead>(
+    reader: &mut Reader<B>,
+    siteinfo: &SiteInfo,
+) -> Result<Option<Page>, ParseError> {
     let mut buf = Vec::new();
     let mut title = None;
+    let mut namespace = None;
+    let mut redirect = None;
+    let mut revision = None;
     let mut content = None;
-    loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) => {
-                let mut buf = Vec::new();
-                match e.name().as_ref() {
-                    b"title" => match reader.read_event_into(&mut buf) {
-                        Ok(Event::Text(e)) => title = Some(e.unescape().unwrap().to_string()),
-                        _ => (),
-                    },
+    let mut parse = || -> Result<(), ParseErrorKind> {
+        loop {
+            match read_event(reader, &mut buf)? {
+                Event::Empty(ref e) if e.name().as_ref() == b"redirect" => {
+                    redirect = match e.try_get_attribute("title")? {
+                        Some(title) => Some(title.unescape_value()?.to_string()),
+                        None => None,
+                    };
+                }
+                Event::Start(ref e) => match e.name().as_ref() {
+                    b"title" => title = read_text(reader)?,
+                    b"ns" => {
+                        namespace = read_text(reader)?
+                            .and_then(|ns| ns.parse::<i32>().ok())
+                            .map(|id| siteinfo.namespace(id))
+                    }
                     b"revision" => {
-                        content = parse_revision(&mut reader);
+                        let (rev, text) = parse_revision(reader)?;
+                        revision = Some(rev);
+                        content = text;
                     }
                     _ => (),
-                }
+                },
+                Event::End(ref e) if e.name().as_ref() == b"page" => return Ok(()),
+                _ => (),
             }
-            Ok(Event::End(ref e)) if e.name().as_ref() == b"page" => break,
-            _ => (),
+            buf.clear();
         }
+    };
+    if let Err(kind) = parse() {
+        return Err(ParseError::new(kind, reader, title));
     }
     // and_then is a poor name for >>=
-    title.and_then(|title| content.map(|content| Page { title, content }))
+    Ok(title.and_then(|title| {
+        content.map(|content| Page {
+            namespace: namespace.unwrap_or_else(|| siteinfo.namespace_of_title(&title)),
+            title,
+            redirect,
+            revision: revision.unwrap_or_default(),
+            content,
+        })
+    }))
+}
+
+/// Makes an XML reader for a whole dump, or for a `fragment` of one like a multistream block.
+fn new_reader<B: BufRead>(input: B, fragment: bool) -> Reader<B> {
+    let mut reader = Reader::from_reader(input);
+    // Blocks are fragments of the document, so closing tags won't always have a matching start.
+    reader.check_end_names(!fragment);
+    reader
+}
+
+/// Skips past the first of `tags` in the raw bytes of `input`, returning how many bytes were
+/// skipped and which of them it was, if any turned up. None of the tags can have a `<` after the
+/// first byte.
+fn skip_past<B: BufRead>(input: &mut B, tags: &[&[u8]]) -> io::Result<(u64, Option<usize>)> {
+    let mut matched = vec![0; tags.len()];
+    let mut skipped = 0;
+    loop {
+        let buf = input.fill_buf()?;
+        if buf.is_empty() {
+            return Ok((skipped, None));
+        }
+        let mut used = 0;
+        let mut found = None;
+        for &byte in buf {
+            used += 1;
+            for (tag, matched) in tags.iter().zip(matched.iter_mut()) {
+                *matched = if byte == tag[*matched] {
+                    *matched + 1
+                } else if byte == tag[0] {
+                    1
+                } else {
+                    0
+                };
+            }
+            found = matched
+                .iter()
+                .zip(tags)
+                .position(|(&n, tag)| n == tag.len());
+            if found.is_some() {
+                break;
+            }
+        }
+        input.consume(used);
+        skipped += used as u64;
+        if found.is_some() {
+            return Ok((skipped, found));
+        }
+    }
 }
 
-pub fn for_pages<F>(filename: &str, mut f: F)
+/// Reads pages until the end of the input, picking up namespace names from the `<siteinfo>`
+/// header if the input contains one. Errors are handed to `on_error`, which either returns them
+/// to stop reading or `Ok(())` to skip to the next page. Running out of input in the middle of a
+/// page, or before the end of a whole dump, is always an error.
+fn read_pages<B: BufRead, F, E>(
+    input: B,
+    fragment: bool,
+    siteinfo: &mut SiteInfo,
+    f: &mut F,
+    on_error: &mut E,
+) -> Result<(), ParseError>
 where
-    F: FnMut(Page) -> (),
+    F: FnMut(Page),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
 {
+    let mut reader = new_reader(input, fragment);
     let mut buf = Vec::new();
-    let mut reader = Reader::from_file(Path::new(filename)).unwrap();
-    'read_words: loop {
-        match reader.read_event_into(&mut buf) {
-            Ok(Event::Start(ref e)) => match e.name().as_ref() {
-                b"page" => {
-                    let page = parse_page(&mut reader);
-                    page.map(|page| f(page));
+    // Where `reader` started in the input, as it's replaced after errors.
+    let mut offset = 0;
+    // Whether the start tag of a page was just skipped past without the reader seeing it.
+    let mut in_page = false;
+    let mut ended = fragment;
+    loop {
+        let result = if in_page {
+            in_page = false;
+            parse_page(&mut reader, siteinfo).map(|page| page.map_or((), &mut *f))
+        } else {
+            match reader.read_event_into(&mut buf) {
+                Ok(Event::Start(ref e)) => match e.name().as_ref() {
+                    b"mediawiki" => {
+                        ended = fragment;
+                        Ok(())
+                    }
+                    b"siteinfo" => parse_siteinfo(&mut reader).map(|s| *siteinfo = s),
+                    b"page" => {
+                        parse_page(&mut reader, siteinfo).map(|page| page.map_or((), &mut *f))
+                    }
+                    _ => Ok(()),
+                },
+                Ok(Event::End(ref e)) if e.name().as_ref() == b"mediawiki" => {
+                    ended = true;
+                    Ok(())
                 }
-                _ => (),
+                Ok(Event::Eof) if ended => break,
+                Ok(Event::Eof) => Err(ParseError::new(
+                    ParseErrorKind::UnexpectedEof,
+                    &reader,
+                    None,
+                )),
+                Err(e) => Err(ParseError::new(ParseErrorKind::Xml(e), &reader, None)),
+                _ => Ok(()),
+            }
+        };
+        buf.clear();
+        if let Err(mut e) = result {
+            e.position += offset;
+            if let ParseErrorKind::UnexpectedEof = e.kind {
+                return Err(e);
+            }
+            on_error(e)?;
+            // quick-xml stops for good after a syntax error, so look for the next page in the
+            // raw input and start reading again from there.
+            offset += reader.buffer_position() as u64;
+            let mut input = reader.into_inner();
+            let (skipped, found) = skip_past(&mut input, &[b"<page>", b"</mediawiki>"])?;
+            offset += skipped;
+            match found {
+                Some(0) => in_page = true,
+                Some(_) => ended = true,
+                None if ended => break,
+                None => {
+                    return Err(ParseError {
+                        kind: ParseErrorKind::UnexpectedEof,
+                        position: offset,
+                        block: None,
+                        title: None,
+                    })
+                }
+            }
+            // That's in the middle of the document, so its closing tags have no start tags.
+            reader = new_reader(input, true);
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` on every page in the dump at `filename`. Compressed dumps are decompressed as they
+/// are read, so they never need to be extracted to disk.
+///
+/// Pages that can't be parsed are passed to `on_error` as a `ParseError`. Returning the error
+/// stops reading and makes `for_pages` return it; returning `Ok(())` skips the page. Once the
+/// whole dump is read, what its `<siteinfo>` header said is returned.
+pub fn for_pages<F, E>(filename: &str, f: F, on_error: E) -> Result<SiteInfo, ParseError>
+where
+    F: FnMut(Page),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    for_pages_in(open_dump(Path::new(filename))?, f, on_error)
+}
+
+/// Like `for_pages`, but reads the dump from `input`, which can be stdin, an in-memory buffer
+/// or anything else. `input` has to be plain XML; pass it through `decompress` first if it
+/// might be compressed.
+pub fn for_pages_in<R, F, E>(input: R, mut f: F, mut on_error: E) -> Result<SiteInfo, ParseError>
+where
+    R: BufRead,
+    F: FnMut(Page),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    let mut siteinfo = SiteInfo::default();
+    read_pages(input, false, &mut siteinfo, &mut f, &mut on_error)?;
+    Ok(siteinfo)
+}
+
+/// Reads a multistream index (lines of `offset:page_id:title`) and returns the distinct byte
+/// offsets of the bz2 blocks in the dump, in order.
+pub fn read_multistream_index(path: &Path) -> io::Result<Vec<u64>> {
+    let mut offsets = Vec::new();
+    for line in open_dump(path)?.lines() {
+        let line = line?;
+        let offset = line
+            .split(':')
+            .next()
+            .and_then(|offset| offset.parse::<u64>().ok())
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Bad multistream index line: {}", line),
+                )
+            })?;
+        if offsets.last() != Some(&offset) {
+            offsets.push(offset);
+        }
+    }
+    offsets.sort_unstable();
+    offsets.dedup();
+    Ok(offsets)
+}
+
+/// Opens the bz2 block of `filename` that starts at `start` and runs until `end`, or until the
+/// end of the file if there is no next block.
+fn open_block(filename: &Path, start: u64, end: Option<u64>) -> io::Result<Box<dyn BufRead>> {
+    let mut file = File::open(filename)?;
+    file.seek(SeekFrom::Start(start))?;
+    let block: Box<dyn BufRead> = match end {
+        Some(end) => Box::new(BufReader::new(BzDecoder::new(file.take(end - start)))),
+        // The last block is followed by a separate stream holding the closing </mediawiki>.
+        None => Box::new(BufReader::new(MultiBzDecoder::new(file))),
+    };
+    Ok(block)
+}
+
+/// Parses every page in a block, keeping errors in line with the pages so that they can be
+/// reported in order.
+fn parse_block<T, P>(
+    filename: &Path,
+    start: u64,
+    end: Option<u64>,
+    siteinfo: &SiteInfo,
+    map: &P,
+) -> Vec<Result<T, ParseError>>
+where
+    P: Fn(Page) -> T,
+{
+    let with_block = |mut e: ParseError| {
+        e.block = Some(start);
+        e
+    };
+    let block = match open_block(filename, start, end) {
+        Ok(block) => block,
+        Err(e) => return vec![Err(with_block(ParseError::from(e)))],
+    };
+    let result = RefCell::new(Vec::new());
+    let read = read_pages(
+        block,
+        true,
+        &mut siteinfo.clone(),
+        &mut |page| result.borrow_mut().push(Ok(map(page))),
+        &mut |e| {
+            result.borrow_mut().push(Err(with_block(e)));
+            Ok(())
+        },
+    );
+    let mut result = result.into_inner();
+    if let Err(e) = read {
+        result.push(Err(with_block(e)));
+    }
+    result
+}
+
+/// Reads the `<siteinfo>` header of a multistream dump, which is in the stream before the first
+/// block.
+fn read_header<E>(
+    filename: &Path,
+    offsets: &[u64],
+    on_error: &mut E,
+) -> Result<SiteInfo, ParseError>
+where
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    let mut siteinfo = SiteInfo::default();
+    if let Some(&first) = offsets.first() {
+        let header = open_block(filename, 0, Some(first))?;
+        read_pages(header, true, &mut siteinfo, &mut |_| (), on_error)?;
+    }
+    Ok(siteinfo)
+}
+
+/// Calls `f` on the pages of a multistream dump whose titles `wanted` picks out, only reading
+/// the blocks that the index at `index_filename` says they're in.
+pub fn for_pages_titled<W, F, E>(
+    filename: &str,
+    index_filename: &str,
+    wanted: W,
+    mut f: F,
+    mut on_error: E,
+) -> Result<(), ParseError>
+where
+    W: Fn(&str) -> bool,
+    F: FnMut(Page),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    let offsets = read_multistream_index(Path::new(index_filename))?;
+    let mut blocks = Vec::new();
+    for line in open_dump(Path::new(index_filename))?.lines() {
+        let line = line?;
+        let mut fields = line.splitn(3, ':');
+        let offset = fields.next().and_then(|offset| offset.parse::<u64>().ok());
+        if let (Some(offset), Some(title)) = (offset, fields.nth(1)) {
+            if wanted(title) {
+                blocks.push(offset);
+            }
+        }
+    }
+    blocks.sort_unstable();
+    blocks.dedup();
+    let filename = Path::new(filename);
+    let siteinfo = read_header(filename, &offsets, &mut on_error)?;
+    for start in blocks {
+        let end = offsets.iter().find(|&&offset| offset > start).cloned();
+        for page in parse_block(filename, start, end, &siteinfo, &|page| page) {
+            match page {
+                Ok(page) if wanted(&page.title) => f(page),
+                Ok(_) => (),
+                Err(e) => on_error(e)?,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Like `for_pages`, but for `pages-articles-multistream.xml.bz2` dumps. Blocks listed in the
+/// index at `index_filename` are decompressed and parsed on `threads` worker threads, each page
+/// is passed through `map` on the worker, and `f` is called on the results in dump order.
+/// `bytes_read` is kept up to date with how much of the dump has been handled so far.
+pub fn for_pages_multistream<T, P, F, E>(
+    filename: &str,
+    index_filename: &str,
+    threads: usize,
+    bytes_read: &AtomicU64,
+    map: P,
+    mut f: F,
+    mut on_error: E,
+) -> Result<SiteInfo, ParseError>
+where
+    T: Send,
+    P: Fn(Page) -> T + Sync,
+    F: FnMut(T),
+    E: FnMut(ParseError) -> Result<(), ParseError>,
+{
+    let offsets = read_multistream_index(Path::new(index_filename))?;
+    let filename = Path::new(filename);
+    let siteinfo = read_header(filename, &offsets, &mut on_error)?;
+    let next_block = AtomicUsize::new(0);
+    let threads = threads.max(1);
+    let (tx, rx) = mpsc::sync_channel(threads * 4);
+    // Workers don't start a block more than `window` blocks after the first one that isn't done,
+    // so that one slow block doesn't leave everything after it piling up in memory.
+    let window = threads * 4;
+    let done = (Mutex::new(0usize), Condvar::new());
+
+    let result = thread::scope(|scope| {
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (offsets, next_block, siteinfo, map) = (&offsets, &next_block, &siteinfo, &map);
+            let done = &done;
+            scope.spawn(move || loop {
+                let i = next_block.fetch_add(1, Ordering::SeqCst);
+                if i >= offsets.len() {
+                    break;
+                }
+                let mut finished = done.0.lock().unwrap();
+                while i >= finished.saturating_add(window) {
+                    finished = done.1.wait(finished).unwrap();
+                }
+                drop(finished);
+                let end = offsets.get(i + 1).cloned();
+                let pages = parse_block(filename, offsets[i], end, siteinfo, map);
+                if tx.send((i, pages)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(tx);
+
+        // Blocks finish out of order, so hold on to them until everything before them is done.
+        // Returning early drops the receiver, which makes the workers stop.
+        let result = (|| {
+            let mut pending = BTreeMap::new();
+            let mut next = 0;
+            for (i, pages) in rx {
+                pending.insert(i, pages);
+                while let Some(pages) = pending.remove(&next) {
+                    for page in pages {
+                        match page {
+                            Ok(page) => f(page),
+                            Err(e) => on_error(e)?,
+                        }
+                    }
+                    next += 1;
+                    *done.0.lock().unwrap() = next;
+                    done.1.notify_all();
+                    if let Some(&end) = offsets.get(next) {
+                        bytes_read.store(end, Ordering::Relaxed);
+                    }
+                }
+            }
+            Ok(())
+        })();
+        // Workers waiting for their turn have nothing left to do either way.
+        *done.0.lock().unwrap() = usize::MAX;
+        done.1.notify_all();
+        result
+    });
+    result.map(|()| siteinfo)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parse_xml::bzip2::write::BzEncoder;
+    use parse_xml::flate2::write::GzEncoder;
+    use std::io::Write;
+
+    /// Parses every page in `xml`, reading it a few bytes at a time so that quick-xml has to
+    /// split up the text events.
+    fn pages(xml: &str) -> Vec<Page> {
+        let input = BufReader::with_capacity(4, xml.as_bytes());
+        let mut pages = Vec::new();
+        for_pages_in(input, |page| pages.push(page), Err).unwrap();
+        pages
+    }
+
+    fn page(title: &str, text: &str) -> String {
+        format!(
+            "<mediawiki><page><title>{}</title><ns>0</ns><revision><id>1</id>\
+             <text xml:space=\"preserve\">{}</text></revision></page></mediawiki>",
+            title, text
+        )
+    }
+
+    #[test]
+    fn long_text_is_kept_whole() {
+        let text = "==English==\n# a definition\n".repeat(1000);
+        let pages = pages(&page("word", &text));
+        assert_eq!(pages[0].content, text);
+    }
+
+    #[test]
+    fn escaped_text_is_unescaped() {
+        let pages = pages(&page("&lt;b&gt; &amp; co", "a &lt;b&gt; &amp; c &#233;"));
+        assert_eq!(pages[0].title, "<b> & co");
+        assert_eq!(pages[0].content, "a <b> & c é");
+    }
+
+    #[test]
+    fn cdata_is_joined_with_text() {
+        let pages = pages(&page(
+            "a<![CDATA[<b>]]>c",
+            "before <![CDATA[{{t|<x>}} & ]]>after",
+        ));
+        assert_eq!(pages[0].title, "a<b>c");
+        assert_eq!(pages[0].content, "before {{t|<x>}} & after");
+    }
+
+    #[test]
+    fn text_around_comments_is_joined() {
+        let pages = pages(&page("word", "one<!-- dropped -->two"));
+        assert_eq!(pages[0].content, "onetwo");
+    }
+
+    #[test]
+    fn empty_text_means_no_page() {
+        assert!(pages(&page("word", "")).is_empty());
+    }
+    #[test]
+    fn pages_after_a_malformed_one_are_still_read() {
+        let xml = page("bad", "text").replace("</title>", "</titel>") + &page("good", "text");
+        let input = BufReader::with_capacity(4, xml.as_bytes());
+        let (mut titles, mut errors) = (Vec::new(), 0);
+        let read = for_pages_in(
+            input,
+            |page| titles.push(page.title),
+            |_| {
+                errors += 1;
+                Ok(())
             },
-            Ok(Event::Eof) => break 'read_words,
-            Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-            _ => (),
+        );
+        assert!(read.is_ok(), "{:?}", read);
+        assert_eq!(errors, 1);
+        assert_eq!(titles, vec!["good"]);
+    }
+
+    #[test]
+    fn malformed_last_pages_are_skipped() {
+        let xml = page("good", "text") + &page("bad", "text").replace("</title>", "</titel>");
+        let input = BufReader::with_capacity(4, xml.as_bytes());
+        let mut titles = Vec::new();
+        let read = for_pages_in(input, |page| titles.push(page.title), |_| Ok(()));
+        assert!(read.is_ok(), "{:?}", read);
+        assert_eq!(titles, vec!["good"]);
+    }
+
+    #[test]
+    fn truncated_dumps_are_an_error() {
+        let xml = page("one", "text") + &page("two", "more text");
+        for end in &[xml.len() - 30, xml.len() - "</mediawiki>".len()] {
+            let input = BufReader::with_capacity(4, &xml.as_bytes()[..*end]);
+            let read = for_pages_in(input, |_| (), |_| Ok(()));
+            match read {
+                Err(ParseError {
+                    kind: ParseErrorKind::UnexpectedEof,
+                    ..
+                }) => (),
+                read => panic!("{:?} at {}", read, end),
+            }
         }
-        buf.clear();
+    }
+    #[test]
+    fn compressed_dumps_are_decompressed() {
+        let xml = page("word", "text");
+        let mut bzip2 = BzEncoder::new(Vec::new(), bzip2::Compression::default());
+        bzip2.write_all(xml.as_bytes()).unwrap();
+        let mut gzip = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gzip.write_all(xml.as_bytes()).unwrap();
+        let dumps = vec![
+            (Compression::None, xml.as_bytes().to_vec()),
+            (Compression::Bzip2, bzip2.finish().unwrap()),
+            (Compression::Gzip, gzip.finish().unwrap()),
+            (
+                Compression::Zstd,
+                zstd::encode_all(xml.as_bytes(), 0).unwrap(),
+            ),
+        ];
+        for (compression, dump) in dumps {
+            assert_eq!(Compression::detect(&dump, None), compression);
+            let mut titles = Vec::new();
+            let input = decompress(&dump[..], None).unwrap();
+            for_pages_in(input, |page| titles.push(page.title), Err).unwrap();
+            assert_eq!(titles, vec!["word"], "{:?}", compression);
+        }
+        // The extension is only used when the bytes don't say.
+        let path = Path::new("dump.xml.gz");
+        assert_eq!(Compression::detect(b"\x1f\x8b", None), Compression::Gzip);
+        assert_eq!(Compression::detect(b"<?xml", Some(path)), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(b"BZh91", Some(path)),
+            Compression::Bzip2
+        );
+    }
+    #[test]
+    fn namespaces_come_from_the_siteinfo() {
+        let siteinfo = "<mediawiki><siteinfo><namespaces>\
+                        <namespace key=\"0\" case=\"case-sensitive\" />\
+                        <namespace key=\"10\" case=\"case-sensitive\">Modèle</namespace>\
+                        <namespace key=\"828\" case=\"case-sensitive\">Module</namespace>\
+                        </namespaces></siteinfo>";
+        let xml = page("Modèle:fr-nom", "text")
+            .replace("<mediawiki>", siteinfo)
+            .replace("<ns>0</ns>", "<ns>10</ns>")
+            + &page("Modèle:sans ns", "text").replace("<ns>0</ns>", "")
+            + &page("Module:languages/data2", "text").replace("<ns>0</ns>", "<ns>828</ns>")
+            + &page("Template:pas un modèle", "text").replace("<ns>0</ns>", "");
+        let pages: Vec<_> = pages(&xml)
+            .iter()
+            .map(|page| (page.namespace.id, page.name().to_owned()))
+            .collect();
+        assert_eq!(
+            pages,
+            vec![
+                (10, "fr-nom".to_owned()),
+                (10, "sans ns".to_owned()),
+                (828, "languages/data2".to_owned()),
+                (0, "Template:pas un modèle".to_owned()),
+            ]
+        );
+    }
+    #[test]
+    fn the_base_url_comes_from_the_siteinfo() {
+        let xml = page("word", "text").replace(
+            "<mediawiki>",
+            "<mediawiki><siteinfo>\
+             <base>https://fr.wiktionary.org/wiki/Wiktionnaire:Page_d%E2%80%99accueil</base>\
+             </siteinfo>",
+        );
+        let siteinfo = for_pages_in(xml.as_bytes(), |_| {}, Err).unwrap();
+        assert_eq!(
+            siteinfo.base.as_deref(),
+            Some("https://fr.wiktionary.org/wiki/Wiktionnaire:Page_d%E2%80%99accueil")
+        );
+        let siteinfo = for_pages_in(page("word", "text").as_bytes(), |_| {}, Err).unwrap();
+        assert_eq!(siteinfo.base, None);
+    }
+    #[test]
+    fn redirects_keep_their_target() {
+        let xml = page("colour", "#REDIRECT [[color#English]]").replace(
+            "<ns>0</ns>",
+            "<ns>0</ns><redirect title=\"color#English &amp; more\" />",
+        ) + &page("color", "text");
+        let pages = pages(&xml);
+        assert_eq!(pages[0].redirect.as_deref(), Some("color#English & more"));
+        assert_eq!(pages[1].redirect, None);
+    }
+    #[test]
+    fn revisions_keep_their_metadata() {
+        let revision = |contributor: &str| {
+            format!(
+                "<ns>0</ns><id>3</id><revision><id>42</id><parentid>41</parentid>\
+                 <timestamp>2024-01-02T03:04:05Z</timestamp>\
+                 <contributor>{}</contributor><sha1>abc123</sha1>",
+                contributor
+            )
+        };
+        let xml = page("one", "text").replace(
+            "<ns>0</ns><revision><id>1</id>",
+            &revision("<username>Someone</username><id>7</id>"),
+        ) + &page("two", "text").replace(
+            "<ns>0</ns><revision><id>1</id>",
+            &revision("<ip>192.0.2.1</ip>"),
+        );
+        let pages = pages(&xml);
+        assert_eq!(pages[0].revision.id, 42);
+        assert_eq!(pages[0].revision.timestamp, "2024-01-02T03:04:05Z");
+        assert_eq!(pages[0].revision.sha1, "abc123");
+        assert_eq!(pages[0].revision.contributor.as_deref(), Some("Someone"));
+        assert_eq!(pages[1].revision.contributor.as_deref(), Some("192.0.2.1"));
     }
 }
@@ -0,0 +1,121 @@
+extern crate rusqlite;
+use self::rusqlite::Connection;
+
+use std::collections::{HashSet, VecDeque};
+
+use RelationKind;
+
+/// The words directly related to `word` in `language`, from the `relations` table of a database
+/// made by build_definitions_db. They're listed in the language's own section, so they're in that
+/// language too.
+pub fn related(conn: &Connection, word: &str, language: &str) -> Vec<(RelationKind, String)> {
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT kind, target FROM relations WHERE name = ?1 AND language = ?2 ORDER BY rowid",
+        )
+        .unwrap();
+    stmt.query_map([word, language], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })
+    .unwrap()
+    .filter_map(|row| {
+        let (kind, target) = row.unwrap();
+        Some((RelationKind::from_name(&kind)?, target))
+    })
+    .collect()
+}
+
+/// Walks the graph of related words in `language` breadth-first from `start`, following
+/// relations of the given kinds for up to `max_depth` steps. `related` gives the words directly
+/// related to a word in a language, like [`related`] does from the database. Returns every word
+/// reached, with how many steps away it is, nearest first.
+pub fn walk_relations<F>(
+    start: &str,
+    language: &str,
+    kinds: &[RelationKind],
+    max_depth: usize,
+    mut related: F,
+) -> Vec<(String, usize)>
+where
+    F: FnMut(&str, &str) -> Vec<(RelationKind, String)>,
+{
+    let mut result = Vec::new();
+    // The whole walk stays in `language`, so words can be told apart by name alone.
+    let mut seen: HashSet<String> = HashSet::new();
+    seen.insert(start.to_owned());
+    let mut queue: VecDeque<(String, usize)> = VecDeque::new();
+    queue.push_back((start.to_owned(), 0));
+    while let Some((word, depth)) = queue.pop_front() {
+        if depth == max_depth {
+            continue;
+        }
+        for (kind, target) in related(&word, language) {
+            if kinds.contains(&kind) && seen.insert(target.clone()) {
+                result.push((target.clone(), depth + 1));
+                queue.push_back((target, depth + 1));
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn walks_only_the_kinds_asked_for() {
+        let mut graph: HashMap<&str, Vec<(RelationKind, String)>> = HashMap::new();
+        graph.insert(
+            "big",
+            vec![
+                (RelationKind::Synonym, "large".to_owned()),
+                (RelationKind::Antonym, "small".to_owned()),
+            ],
+        );
+        graph.insert(
+            "large",
+            vec![
+                (RelationKind::Synonym, "huge".to_owned()),
+                (RelationKind::Synonym, "big".to_owned()),
+            ],
+        );
+        graph.insert("huge", vec![(RelationKind::Synonym, "vast".to_owned())]);
+        let related = |word: &str, _: &str| graph.get(word).cloned().unwrap_or_default();
+
+        assert_eq!(
+            walk_relations("big", "English", &[RelationKind::Synonym], 2, related),
+            vec![("large".to_owned(), 1), ("huge".to_owned(), 2)]
+        );
+        assert_eq!(
+            walk_relations("big", "English", RelationKind::ALL, 1, related),
+            vec![("large".to_owned(), 1), ("small".to_owned(), 1)]
+        );
+    }
+
+    #[test]
+    fn walks_the_database_in_one_language() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE relations (name text, language text, kind text, target text);
+             INSERT INTO relations VALUES
+                 ('chat', 'English', 'synonym', 'talk'),
+                 ('chat', 'French', 'synonym', 'matou'),
+                 ('talk', 'English', 'synonym', 'speak'),
+                 ('matou', 'French', 'hypernym', 'chat'),
+                 ('speak', 'English', 'synonym', 'chat');",
+        )
+        .unwrap();
+        let walk = |language: &str| {
+            walk_relations("chat", language, RelationKind::ALL, 3, |word, language| {
+                related(&conn, word, language)
+            })
+        };
+        assert_eq!(
+            walk("English"),
+            vec![("talk".to_owned(), 1), ("speak".to_owned(), 2)]
+        );
+        assert_eq!(walk("French"), vec![("matou".to_owned(), 1)]);
+    }
+}
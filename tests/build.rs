@@ -0,0 +1,203 @@
+//! Runs build_definitions_db on small dumps, the way it's run from the command line, and
+//! sometimes define on what it built.
+extern crate bzip2;
+extern crate rusqlite;
+
+use bzip2::write::BzEncoder;
+use bzip2::Compression;
+use rusqlite::Connection;
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+fn page(title: &str, namespace: i32, revision: u64, text: &str) -> String {
+    format!(
+        "<page><title>{}</title><ns>{}</ns><revision><id>{}</id>\
+         <timestamp>2020-01-01T00:00:00Z</timestamp><sha1>{}</sha1>\
+         <text xml:space=\"preserve\">{}</text></revision></page>",
+        title, namespace, revision, revision, text
+    )
+}
+
+fn entry(title: &str, revision: u64, definition: &str) -> String {
+    let text = format!("==English==\n===Noun===\n# {}\n", definition);
+    page(title, 0, revision, &text)
+}
+
+const HEADER: &str = "<mediawiki><siteinfo><namespaces><namespace key=\"0\" />\
+                      <namespace key=\"828\">Module</namespace></namespaces></siteinfo>";
+
+fn dump(pages: &[String]) -> String {
+    format!("{}{}</mediawiki>", HEADER, pages.concat())
+}
+
+/// A directory of its own for each test, as they run at the same time.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("define3-{}-{}", test, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs build_definitions_db in `dir` on `dump`, saving the database in `dir`.
+fn build(dir: &Path, dump: &str, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_build_definitions_db"))
+        .args(args)
+        .args(["--progress=none", dump])
+        .env("XDG_DATA_HOME", dir)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+fn words(dir: &Path) -> Vec<String> {
+    let conn = Connection::open(dir.join("define3").join("define3.sqlite3")).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT name, language, gender, definition FROM words ORDER BY name, id")
+        .unwrap();
+    let words = stmt
+        .query_map([], |row| {
+            Ok(format!(
+                "{} ({}, {}): {}",
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?
+            ))
+        })
+        .unwrap()
+        .map(|row| row.unwrap())
+        .collect();
+    words
+}
+
+#[test]
+fn updates_with_skipped_pages_delete_nothing() {
+    let dir = temp_dir("update");
+    let path = dir.join("dump.xml");
+    fs::write(
+        &path,
+        dump(&[
+            entry("cat", 1, "A feline."),
+            entry("dog", 2, "A canine."),
+            entry("cow", 3, "A bovine."),
+        ]),
+    )
+    .unwrap();
+    build(&dir, path.to_str().unwrap(), &[]);
+    // "dog" is broken, and "cow" is gone.
+    fs::write(
+        &path,
+        dump(&[
+            entry("cat", 4, "A small feline."),
+            entry("dog", 5, "A canine.").replace("</title>", "</titel>"),
+        ]),
+    )
+    .unwrap();
+    build(&dir, path.to_str().unwrap(), &["--update", "--skip-errors"]);
+
+    let words = words(&dir);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(
+        words,
+        vec![
+            "cat (English, ): A small feline.",
+            "cow (English, ): A bovine.",
+            "dog (English, ): A canine.",
+        ]
+    );
+}
+
+/// Writes a multistream dump with a bz2 stream for each block of pages, and its index.
+fn multistream(dir: &Path, blocks: &[Vec<String>]) -> (PathBuf, PathBuf) {
+    let bz2 = |text: &str| {
+        let mut encoder = BzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    };
+    let mut dump = bz2(HEADER);
+    let mut index = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        for (j, page) in block.iter().enumerate() {
+            let title = &page[page.find("<title>").unwrap() + 7..page.find("</title>").unwrap()];
+            index.push_str(&format!("{}:{}:{}\n", dump.len(), i * 100 + j, title));
+        }
+        dump.extend(bz2(&block.concat()));
+    }
+    dump.extend(bz2("</mediawiki>"));
+    let (dump_path, index_path) = (dir.join("dump.xml.bz2"), dir.join("index.txt"));
+    fs::write(&dump_path, dump).unwrap();
+    fs::write(&index_path, index).unwrap();
+    (dump_path, index_path)
+}
+
+#[test]
+fn multistream_builds_are_the_same_on_any_number_of_threads() {
+    let dir = temp_dir("multistream");
+    // The code used by the headword templates is only in the last block.
+    let mut blocks: Vec<Vec<String>> = (0..20)
+        .map(|i| {
+            (0..5)
+                .map(|j| {
+                    let text = format!("==Zed==\n===Noun===\n{{{{zz-noun|f}}}}\n# thing {}\n", j);
+                    page(&format!("word{}-{}", i, j), 0, 1, &text)
+                })
+                .collect()
+        })
+        .collect();
+    blocks[0].push(page(
+        "Module:languages/data2",
+        828,
+        1,
+        "local m = {}\nm[\"en\"] = {\"English\"}\nreturn m\n",
+    ));
+    blocks[19].push(page(
+        "Module:languages/data3/z",
+        828,
+        1,
+        "local m = {}\nm[\"zz\"] = {\"Zed\"}\nreturn m\n",
+    ));
+    let (dump, index) = multistream(&dir, &blocks);
+    let (dump, index) = (dump.to_str().unwrap(), index.to_str().unwrap());
+
+    let mut builds = Vec::new();
+    for threads in &["1", "4"] {
+        build(&dir, dump, &["-i", index, "-j", threads]);
+        builds.push(words(&dir));
+    }
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(builds[0].len(), 100);
+    assert!(builds[0].iter().all(|word| word.contains("(Zed, f)")));
+    assert_eq!(builds[0], builds[1]);
+}
+
+#[test]
+fn inflections_list_each_form_once() {
+    let dir = temp_dir("inflections");
+    let path = dir.join("dump.xml");
+    let go = "==English==\n===Verb===\n{{en-verb|goes|going|went|gone}}\n# To move.\n";
+    let went = "==English==\n===Verb===\n{{head|en|verb form}}\n# {{past tense of|en|go}}\n";
+    fs::write(
+        &path,
+        dump(&[page("go", 0, 1, go), page("went", 0, 2, went)]),
+    )
+    .unwrap();
+    build(&dir, path.to_str().unwrap(), &[]);
+
+    let output = Command::new(env!("CARGO_BIN_EXE_define"))
+        .args(["--inflections", "go"])
+        .env("XDG_DATA_HOME", &dir)
+        .env("NO_COLOR", "1")
+        .output()
+        .unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    let output = String::from_utf8(output.stdout).unwrap();
+    let went: Vec<&str> = output
+        .lines()
+        .filter(|line| line.contains("went"))
+        .collect();
+    assert_eq!(went.len(), 1, "{}", output);
+    assert!(went[0].contains("simple past"), "{}", output);
+}